@@ -1,4 +1,4 @@
-use fwupd_dbus::{Client, Signal};
+use fwupd_dbus::{Client, FwupdErrorKind, Signal};
 use std::{
     error::Error,
     process,
@@ -45,13 +45,19 @@ fn main_() -> Result<(), Box<dyn Error>> {
         println!("Device: {} {}", device.vendor, device.name);
 
         if device.is_updateable() {
-            if let Ok(upgrades) = fwupd.upgrades(&device) {
-                println!("  upgrades found");
-                for upgrade in upgrades {
-                    println!("{:#?}", upgrade);
+            match fwupd.upgrades(&device) {
+                Ok(upgrades) => {
+                    println!("  upgrades found");
+                    for upgrade in upgrades {
+                        println!("{:#?}", upgrade);
+                    }
                 }
-            } else {
-                println!("  no updates available");
+                Err(why) => match why.fwupd_error() {
+                    Some(error) if error.kind == FwupdErrorKind::NothingToDo => {
+                        println!("  no updates available")
+                    }
+                    _ => println!("  failed to get upgrades: {}", why),
+                },
             }
 
             if let Ok(downgrades) = fwupd.downgrades(&device) {
@@ -105,11 +111,18 @@ fn listen_in_background(cancellable: Arc<AtomicBool>) {
                     Signal::DeviceRemoved(device) => {
                         println!("device added: {:?}", device);
                     }
-                    Signal::PropertiesChanged { interface, changed, invalidated } => {
-                        println!(
-                            "Properties of {} changed:\n changed: {:?}\n invalidated: {:?}",
-                            interface, changed, invalidated
-                        );
+                    Signal::DeviceRequest(request) => {
+                        let message = request.message.as_ref().map_or("", |m| m.as_ref());
+                        println!("device request for {}: {}", &*request.device_id, message);
+                    }
+                    Signal::StatusChanged(status) => {
+                        println!("status changed: {:?}", status);
+                    }
+                    Signal::PercentageChanged(percent) => {
+                        println!("percentage changed: {}", percent);
+                    }
+                    other => {
+                        println!("property changed: {:?}", other);
                     }
                 }
             }
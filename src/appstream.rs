@@ -0,0 +1,424 @@
+//! Parses the AppStream metadata of a remote, such as the `firmware.xml.gz` published by the
+//! LVFS, so that releases may be looked up without asking the daemon.
+
+use crate::common::GZIP_MAGIC;
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+use xml::{
+    reader::{EventReader, XmlEvent},
+    writer,
+};
+
+/// An error that may occur when parsing AppStream metadata.
+#[derive(Debug, Error)]
+pub enum AppStreamError {
+    #[error(display = "expected a components or component root element, found {}", _0)]
+    InvalidRoot(Box<str>),
+    #[error(display = "the {} attribute has an invalid value: {}", _0, _1)]
+    InvalidValue(&'static str, Box<str>),
+    #[error(display = "metadata does not have a root element")]
+    NoRoot,
+    #[error(display = "unable to open AppStream metadata at {:?}", _1)]
+    Open(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "failed to read AppStream metadata")]
+    Read(#[error(cause, no_from)] io::Error),
+    #[error(display = "failed to write AppStream metadata")]
+    Write(#[error(cause, no_from)] writer::Error),
+    #[error(display = "failed to parse AppStream metadata")]
+    Xml(#[error(cause, no_from)] xml::reader::Error),
+}
+
+/// The kind of requirement that a component has on the system.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequirementKind {
+    Unknown,
+    /// Requires another component, such as a minimum version of fwupd.
+    Id,
+    /// Requires a version of the firmware on the device, or on another device.
+    Firmware,
+    /// Requires the hardware ID of the host.
+    Hardware,
+    /// Requires a feature of the client.
+    Client,
+}
+
+impl Default for RequirementKind {
+    fn default() -> Self { RequirementKind::Unknown }
+}
+
+impl<'a> From<&'a str> for RequirementKind {
+    fn from(element: &'a str) -> Self {
+        use self::RequirementKind::*;
+        match element {
+            "id" => Id,
+            "firmware" => Firmware,
+            "hardware" => Hardware,
+            "client" => Client,
+            _ => Unknown,
+        }
+    }
+}
+
+/// A requirement that must be met before a component may be installed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Requirement {
+    /// How the version is compared, such as `ge`.
+    pub compare: Option<Box<str>>,
+    pub kind:    RequirementKind,
+    pub value:   Box<str>,
+    pub version: Option<Box<str>>,
+}
+
+/// A checksum of a release, or of the firmware which it contains.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checksum {
+    pub filename: Option<Box<str>>,
+    /// The algorithm of the checksum, such as `sha256`.
+    pub kind:     Box<str>,
+    /// What was hashed: either the `container` cabinet archive, or its `content`.
+    pub target:   Option<Box<str>>,
+    pub value:    Box<str>,
+}
+
+/// A release of a component, as described by the metadata of a remote.
+#[derive(Clone, Debug, Default)]
+pub struct ComponentRelease {
+    pub checksums:        Vec<Checksum>,
+    pub description:      Option<Box<str>>,
+    pub install_duration: u32,
+    /// The URIs of every mirror of the release, in order of preference.
+    pub locations:        Vec<Box<str>>,
+    /// The size of the cabinet archive that is downloaded.
+    pub size:             u64,
+    pub timestamp:        u64,
+    pub urgency:          Option<Box<str>>,
+    pub version:          Box<str>,
+}
+
+impl ComponentRelease {
+    /// The checksums of the cabinet archive, which the downloaded file is validated against.
+    pub fn container_checksums(&self) -> impl Iterator<Item = &Checksum> {
+        self.checksums
+            .iter()
+            .filter(|checksum| checksum.target.as_ref().map_or(true, |t| &**t == "container"))
+    }
+}
+
+/// A firmware component, as described by the metadata of a remote.
+#[derive(Clone, Debug, Default)]
+pub struct Component {
+    pub categories:      Vec<Box<str>>,
+    pub description:     Option<Box<str>>,
+    pub developer_name:  Option<Box<str>>,
+    pub homepage:        Option<Box<str>>,
+    pub id:              Box<str>,
+    pub name:            Box<str>,
+    pub project_license: Option<Box<str>>,
+    /// The GUIDs of the devices which the firmware may be flashed to.
+    pub provides:        Vec<Box<str>>,
+    pub releases:        Vec<ComponentRelease>,
+    pub requirements:    Vec<Requirement>,
+    pub summary:         Option<Box<str>>,
+}
+
+impl Component {
+    /// If the firmware may be flashed to a device with the given GUID.
+    pub fn provides_guid(&self, guid: &str) -> bool {
+        self.provides.iter().any(|provided| provided.eq_ignore_ascii_case(guid))
+    }
+}
+
+/// The components described by the AppStream metadata of a remote.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    pub components: Vec<Component>,
+}
+
+impl Metadata {
+    /// Opens metadata from a file, which may be compressed with gzip.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, AppStreamError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|why| AppStreamError::Open(why, path.to_path_buf()))?;
+        Self::from_reader(file)
+    }
+
+    /// Parses metadata which may be compressed with gzip.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, AppStreamError> {
+        let mut reader = BufReader::new(reader);
+        let compressed = reader.fill_buf().map_err(AppStreamError::Read)?.starts_with(GZIP_MAGIC);
+
+        if compressed {
+            Self::from_xml(GzDecoder::new(reader))
+        } else {
+            Self::from_xml(reader)
+        }
+    }
+
+    /// Parses uncompressed metadata, whose root is either `<components>` or a `<component>`.
+    pub fn from_xml<R: Read>(reader: R) -> Result<Self, AppStreamError> {
+        let root = Element::parse(reader)?;
+
+        let components = match &*root.name {
+            "components" => root.children("component").map(component).collect::<Result<_, _>>()?,
+            "component" => vec![component(&root)?],
+            other => return Err(AppStreamError::InvalidRoot(other.into())),
+        };
+
+        Ok(Metadata { components })
+    }
+
+    /// The components which provide firmware for a device with the given GUID.
+    pub fn components_for_guid<'a>(
+        &'a self,
+        guid: &'a str,
+    ) -> impl Iterator<Item = &'a Component> + 'a {
+        self.components.iter().filter(move |component| component.provides_guid(guid))
+    }
+
+    /// Every release of firmware for a device with the given GUID, paired with its component.
+    pub fn releases_for_guid<'a>(
+        &'a self,
+        guid: &'a str,
+    ) -> Vec<(&'a Component, &'a ComponentRelease)> {
+        self.components_for_guid(guid)
+            .flat_map(|component| component.releases.iter().map(move |r| (component, r)))
+            .collect()
+    }
+}
+
+fn component(element: &Element) -> Result<Component, AppStreamError> {
+    let mut component = Component::default();
+
+    for child in element.elements.iter().filter(|child| !child.is_translation()) {
+        match &*child.name {
+            "categories" => {
+                component.categories = child.children("category").map(Element::text).collect()
+            }
+            "description" => component.description = Some(child.markup_text()),
+            "developer_name" => component.developer_name = Some(child.text()),
+            "id" => component.id = child.text(),
+            "name" => component.name = child.text(),
+            "project_license" => component.project_license = Some(child.text()),
+            "provides" => {
+                component.provides = child.children("firmware").map(Element::text).collect()
+            }
+            "releases" => {
+                component.releases =
+                    child.children("release").map(release).collect::<Result<_, _>>()?
+            }
+            "requires" => {
+                component.requirements = child.elements.iter().map(requirement).collect()
+            }
+            "summary" => component.summary = Some(child.text()),
+            "url" if child.attribute("type") == Some("homepage") => {
+                component.homepage = Some(child.text())
+            }
+            _ => (),
+        }
+    }
+
+    Ok(component)
+}
+
+fn release(element: &Element) -> Result<ComponentRelease, AppStreamError> {
+    let mut release = ComponentRelease {
+        install_duration: element.parse_attribute("install_duration")?.unwrap_or(0),
+        timestamp:        element.parse_attribute("timestamp")?.unwrap_or(0),
+        urgency:          element.attribute("urgency").map(Box::from),
+        version:          element.attribute("version").unwrap_or_default().into(),
+        ..Default::default()
+    };
+
+    for child in element.elements.iter().filter(|child| !child.is_translation()) {
+        match &*child.name {
+            "checksum" => release.checksums.push(Checksum {
+                filename: child.attribute("filename").map(Box::from),
+                kind:     child.attribute("type").unwrap_or_default().into(),
+                target:   child.attribute("target").map(Box::from),
+                value:    child.text(),
+            }),
+            "description" => release.description = Some(child.markup_text()),
+            "location" => release.locations.push(child.text()),
+            "size" if child.attribute("type") == Some("download") => {
+                let size = child.text();
+                release.size =
+                    size.parse().map_err(|_| AppStreamError::InvalidValue("size", size))?;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(release)
+}
+
+fn requirement(element: &Element) -> Requirement {
+    Requirement {
+        compare: element.attribute("compare").map(Box::from),
+        kind:    RequirementKind::from(&*element.name),
+        value:   element.text(),
+        version: element.attribute("version").map(Box::from),
+    }
+}
+
+/// An element of the XML document, which is parsed in full before the components are read.
+#[derive(Debug, Default)]
+struct Element {
+    attributes: Vec<(Box<str>, Box<str>)>,
+    elements:   Vec<Element>,
+    /// Set if the element is a translation, with the `xml:lang` attribute.
+    lang:       Option<Box<str>>,
+    name:       Box<str>,
+    text:       String,
+}
+
+impl Element {
+    fn parse<R: Read>(reader: R) -> Result<Self, AppStreamError> {
+        let mut stack: Vec<Element> = Vec::new();
+
+        for event in EventReader::new(reader) {
+            match event.map_err(AppStreamError::Xml)? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let name = name.local_name.into();
+                    let mut element = Element { name, ..Default::default() };
+
+                    for attribute in attributes {
+                        if attribute.name.prefix.as_ref().map(String::as_str) == Some("xml")
+                            && attribute.name.local_name == "lang"
+                        {
+                            element.lang = Some(attribute.value.into());
+                        } else {
+                            let name = attribute.name.local_name.into();
+                            element.attributes.push((name, attribute.value.into()));
+                        }
+                    }
+
+                    stack.push(element);
+                }
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop().expect("end element without start element");
+                    match stack.last_mut() {
+                        Some(parent) => parent.elements.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Err(AppStreamError::NoRoot)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| &**key == name).map(|(_, value)| value.as_ref())
+    }
+
+    fn parse_attribute<T: std::str::FromStr>(
+        &self,
+        name: &'static str,
+    ) -> Result<Option<T>, AppStreamError> {
+        match self.attribute(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| AppStreamError::InvalidValue(name, value.into())),
+            None => Ok(None),
+        }
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.elements
+            .iter()
+            .filter(move |element| &*element.name == name && !element.is_translation())
+    }
+
+    fn is_translation(&self) -> bool { self.lang.as_ref().map_or(false, |lang| &**lang != "C") }
+
+    fn text(&self) -> Box<str> { self.text.trim().into() }
+
+    /// The text of a description, whose paragraphs and list items are placed on separate lines.
+    fn markup_text(&self) -> Box<str> {
+        let mut lines = Vec::new();
+        self.collect_lines(&mut lines);
+        lines.join("\n").into()
+    }
+
+    fn collect_lines<'a>(&'a self, lines: &mut Vec<&'a str>) {
+        let text = self.text.trim();
+        if !text.is_empty() {
+            lines.push(text);
+        }
+
+        for element in self.elements.iter().filter(|element| !element.is_translation()) {
+            element.collect_lines(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APPSTREAM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<components origin="lvfs" version="0.9">
+  <component type="firmware">
+    <id>com.example.Mock.firmware</id>
+    <name>Mock</name>
+    <name xml:lang="de">Attrappe</name>
+    <provides>
+      <firmware type="flashed">2082b5e0-7a64-478a-b1b2-e3404fab6dad</firmware>
+    </provides>
+    <categories><category>X-System</category></categories>
+    <requires>
+      <id compare="ge" version="1.4.0">org.freedesktop.fwupd</id>
+      <hardware>6de5d951-d755-576b-bd09-c5cf66b27234</hardware>
+    </requires>
+    <releases>
+      <release version="1.2.4" timestamp="1587945600" urgency="high">
+        <location>https://example.com/mock-1.2.4.cab</location>
+        <location>https://mirror.example.com/mock-1.2.4.cab</location>
+        <checksum type="sha1" target="container">f0c2d0cd6b4e1bf3c3cb5a6b1e4c0b3e3f0a1b2c</checksum>
+        <checksum type="sha1" target="content" filename="mock.bin">0000</checksum>
+        <description><p>Fixes a bug.</p><ul><li>Faster</li></ul></description>
+        <size type="installed">4096</size>
+        <size type="download">2048</size>
+      </release>
+    </releases>
+  </component>
+</components>"#;
+
+    #[test]
+    fn appstream_metadata() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut encoder, APPSTREAM.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        for data in &[APPSTREAM.as_bytes(), compressed.as_slice()] {
+            let metadata = Metadata::from_reader(*data).unwrap();
+            assert_eq!(metadata.components.len(), 1);
+            assert_eq!(&*metadata.components[0].name, "Mock");
+            assert_eq!(metadata.components[0].requirements[0].kind, RequirementKind::Id);
+
+            let releases = metadata.releases_for_guid("2082B5E0-7A64-478A-B1B2-E3404FAB6DAD");
+            assert_eq!(releases.len(), 1);
+
+            let release = releases[0].1;
+            assert_eq!(&*release.version, "1.2.4");
+            assert_eq!(release.locations.len(), 2);
+            assert_eq!(release.container_checksums().count(), 1);
+            assert_eq!(release.size, 2048);
+            let description = release.description.as_ref().map(AsRef::as_ref);
+            assert_eq!(description, Some("Fixes a bug.\nFaster"));
+
+            assert!(metadata.releases_for_guid("unknown").is_empty());
+        }
+    }
+}
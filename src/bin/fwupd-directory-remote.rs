@@ -0,0 +1,75 @@
+//! Creates a fwupd remote from a directory of cabinet archives, for machines which cannot reach
+//! the LVFS.
+
+use fwupd_dbus::{DirectoryRemoteBuilder, REMOTES_DIR};
+use std::{env, error::Error, process};
+
+const USAGE: &str = "usage: fwupd-directory-remote [--id ID] [--title TITLE] \
+                     [--remotes-dir DIR] [--no-config] DIRECTORY";
+
+fn main() {
+    if let Err(why) = main_() {
+        let mut error = format!("error: {}", why);
+        let mut cause = why.source();
+        while let Some(why) = cause {
+            error.push_str(&format!("\n    caused by: {}", why));
+            cause = why.source();
+        }
+
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn main_() -> Result<(), Box<dyn Error>> {
+    let mut directory = None;
+    let mut remote_id = None;
+    let mut title = None;
+    let mut remotes_dir = String::from(REMOTES_DIR);
+    let mut write_config = true;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--id" => remote_id = Some(args.next().ok_or(USAGE)?),
+            "--title" => title = Some(args.next().ok_or(USAGE)?),
+            "--remotes-dir" => remotes_dir = args.next().ok_or(USAGE)?,
+            "--no-config" => write_config = false,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if directory.is_none() && !arg.starts_with('-') => directory = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let mut builder = DirectoryRemoteBuilder::new(directory.ok_or(USAGE)?);
+
+    if let Some(ref remote_id) = remote_id {
+        builder = builder.remote_id(remote_id);
+    }
+
+    if let Some(ref title) = title {
+        builder = builder.title(title);
+    }
+
+    let remote = builder.build()?;
+
+    for component in &remote.metadata.components {
+        for release in &component.releases {
+            println!("{} {}", component.id, release.version);
+        }
+    }
+
+    println!("wrote {}", remote.metadata_path.display());
+
+    if write_config {
+        let config = remote.write_config(&remotes_dir)?;
+        println!("wrote {}", config.display());
+    } else {
+        print!("{}", remote.config());
+    }
+
+    Ok(())
+}
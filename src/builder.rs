@@ -0,0 +1,148 @@
+use crate::{Client, Error, FeatureFlags, DBUS_NAME, DBUS_PATH};
+use dbus::{
+    ffidisp::Connection,
+    strings::{BusName, Path},
+};
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+/// The bus that a client connects to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Bus {
+    /// The system bus, which the fwupd daemon is normally served on.
+    System,
+    /// The session bus of the current user.
+    Session,
+    /// A bus at an explicit address, such as `unix:path=/run/fwupd/bus`.
+    Address(Box<str>),
+}
+
+impl Default for Bus {
+    fn default() -> Self { Bus::System }
+}
+
+/// Configures how a client connects to the fwupd daemon.
+///
+/// ```no_run
+/// use fwupd_dbus::{Bus, ClientBuilder};
+///
+/// let client = ClientBuilder::new()
+///     .bus(Bus::Address("unix:path=/tmp/fwupd-test-bus".into()))
+///     .bus_name("org.freedesktop.fwupd.Test")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    pub(crate) bus:           Bus,
+    pub(crate) bus_name:      Box<str>,
+    pub(crate) feature_flags: Option<FeatureFlags>,
+    pub(crate) path:          Box<str>,
+    pub(crate) timeout:       Option<Duration>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            bus:           Bus::default(),
+            bus_name:      DBUS_NAME.into(),
+            feature_flags: None,
+            path:          DBUS_PATH.into(),
+            timeout:       None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self { Self::default() }
+
+    /// The bus to connect to, which is the system bus by default.
+    pub fn bus(mut self, bus: Bus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// The well-known name that the daemon owns on the bus.
+    pub fn bus_name(mut self, bus_name: &str) -> Self {
+        self.bus_name = bus_name.into();
+        self
+    }
+
+    /// Declares the features that the client supports to the daemon once connected.
+    ///
+    /// A non-blocking client queues the declaration without waiting for the daemon to reply.
+    pub fn feature_flags(mut self, flags: FeatureFlags) -> Self {
+        self.feature_flags = Some(flags);
+        self
+    }
+
+    /// The object path that the daemon serves its interface on.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// How long method calls wait for a reply, which is forever by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connects to the configured bus.
+    pub fn build(self) -> Result<Client, Error> {
+        self.validate()?;
+
+        let connection = match self.bus {
+            Bus::System => Connection::new_system(),
+            Bus::Session => Connection::new_session(),
+            Bus::Address(ref address) => Connection::open_private(address)
+                .and_then(|connection| connection.register().map(|_| connection)),
+        };
+
+        connection.map_err(Error::Connection).and_then(|connection| self.declare_with(connection))
+    }
+
+    /// Uses a connection which has already been opened, ignoring the configured bus.
+    pub fn build_with(self, connection: Connection) -> Result<Client, Error> {
+        self.validate()?;
+        self.declare_with(connection)
+    }
+
+    /// Creates the client, and declares its features to the daemon.
+    fn declare_with(self, connection: Connection) -> Result<Client, Error> {
+        let feature_flags = self.feature_flags;
+        let client = self.into_client(connection);
+
+        if let Some(flags) = feature_flags {
+            client.set_feature_flags(flags)?;
+        }
+
+        Ok(client)
+    }
+
+    /// Checks the bus name and path, which the client assumes to be valid when calling methods.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        BusName::new(&*self.bus_name).map_err(Error::InvalidBusName)?;
+        Path::new(&*self.path).map_err(Error::InvalidPath)?;
+        Ok(())
+    }
+
+    /// Creates the client, without declaring its features.
+    pub(crate) fn into_client(self, connection: Connection) -> Client {
+        Client {
+            connection: Rc::new(connection),
+            bus_name: self.bus_name,
+            path: self.path,
+            cancellable: None,
+            timeout: self.timeout,
+            user_agent: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Connects a non-blocking client to the configured bus.
+    ///
+    /// Must be called from within a tokio runtime, which will drive the connection.
+    #[cfg(feature = "async")]
+    pub fn build_async(self) -> Result<crate::AsyncClient, Error> {
+        crate::AsyncClient::from_builder(self)
+    }
+}
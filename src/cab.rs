@@ -0,0 +1,662 @@
+//! Reads the Microsoft cabinet archives that firmware is distributed in, so that the metainfo,
+//! payload and detached signatures of a release may be inspected before it is installed.
+//!
+//! Only single-cabinet archives are supported, whose folders are either stored or compressed
+//! with MSZIP.
+
+use crate::{common::*, AppStreamError, Checksum, Metadata};
+use flate2::read::DeflateDecoder;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// The magic bytes at the beginning of a cabinet archive.
+const CAB_MAGIC: &[u8] = b"MSCF";
+
+/// The magic bytes at the beginning of each MSZIP block.
+const MSZIP_MAGIC: &[u8] = b"CK";
+
+/// The size of the window which an MSZIP block may refer back into.
+const MSZIP_WINDOW: usize = 32 * 1024;
+
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+const ATTRIBUTE_NAME_IS_UTF: u16 = 0x0080;
+
+/// File folder indexes at or above this value are continued from, or to, another cabinet.
+const FOLDER_CONTINUED: u16 = 0xFFFD;
+
+/// Suffixes of the detached signatures that may accompany the payload.
+const SIGNATURE_SUFFIXES: &[&str] = &[".asc", ".jcat", ".p7b", ".p7c"];
+
+const METAINFO_SUFFIX: &str = ".metainfo.xml";
+
+/// An error that may occur when reading a cabinet archive.
+#[derive(Debug, Error)]
+pub enum CabError {
+    #[error(display = "the {} checksum of {} does not match", _1, _0)]
+    ChecksumMismatch(Box<str>, Box<str>),
+    #[error(display = "{} is continued in another cabinet, which is not supported", _0)]
+    Continued(Box<str>),
+    #[error(display = "failed to decompress an MSZIP block")]
+    Decompress(#[error(cause, no_from)] io::Error),
+    #[error(display = "the {} folder has invalid data: {}", _0, _1)]
+    InvalidData(u16, &'static str),
+    #[error(display = "failed to parse the metainfo of the cabinet")]
+    Metainfo(#[error(cause, no_from)] AppStreamError),
+    #[error(display = "the cabinet does not contain {}", _0)]
+    NoFile(Box<str>),
+    #[error(display = "the metainfo does not have any content checksums to validate with")]
+    NoChecksums,
+    #[error(display = "the cabinet does not contain a metainfo file")]
+    NoMetainfo,
+    #[error(display = "the cabinet does not contain a firmware payload")]
+    NoPayload,
+    #[error(display = "not a cabinet archive")]
+    NotCabinet,
+    #[error(display = "unable to open cabinet archive at {:?}", _1)]
+    Open(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "failed to read cabinet archive")]
+    Read(#[error(cause, no_from)] io::Error),
+    #[error(display = "the {} folder uses unsupported compression: {:?}", _0, _1)]
+    UnsupportedCompression(u16, CabCompression),
+}
+
+/// How the data of a folder is compressed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CabCompression {
+    None,
+    MsZip,
+    Quantum,
+    Lzx,
+    Unknown(u16),
+}
+
+impl From<u16> for CabCompression {
+    fn from(value: u16) -> Self {
+        use self::CabCompression::*;
+        match value & 0x000F {
+            0 => None,
+            1 => MsZip,
+            2 => Quantum,
+            3 => Lzx,
+            other => Unknown(other),
+        }
+    }
+}
+
+/// A file stored in a cabinet archive.
+#[derive(Clone, Debug)]
+pub struct CabFile {
+    pub attributes: u16,
+    /// The modification date and time, in MS-DOS format.
+    pub date_time:  (u16, u16),
+    pub name:       Box<str>,
+    pub size:       u32,
+    folder:         u16,
+    offset:         u32,
+}
+
+impl CabFile {
+    /// If the file is the metainfo which describes the firmware.
+    pub fn is_metainfo(&self) -> bool { self.name.ends_with(METAINFO_SUFFIX) }
+
+    /// If the file is a detached signature, such as `firmware.bin.asc`.
+    pub fn is_signature(&self) -> bool {
+        SIGNATURE_SUFFIXES.iter().any(|suffix| self.name.ends_with(suffix))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Folder {
+    compression: CabCompression,
+    /// The number of data blocks in the folder.
+    blocks:      u16,
+    /// The offset of the first data block, from the beginning of the cabinet.
+    offset:      u32,
+}
+
+/// A cabinet archive, whose files are decompressed as they are extracted.
+pub struct Cabinet<R> {
+    reader:       R,
+    files:        Vec<CabFile>,
+    folders:      Vec<Folder>,
+    /// The size of the reserved area of each data block.
+    data_reserve: u8,
+    /// The most recently decompressed folder, which the next file is likely to be stored in.
+    cache:        Option<(u16, Vec<u8>)>,
+}
+
+impl Cabinet<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CabError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|why| CabError::Open(why, path.to_path_buf()))?;
+        Self::new(file)
+    }
+}
+
+impl<R: Read + Seek> Cabinet<R> {
+    /// Reads the header, folders and files of the cabinet.
+    pub fn new(mut reader: R) -> Result<Self, CabError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(CabError::Read)?;
+        if magic != CAB_MAGIC {
+            return Err(CabError::NotCabinet);
+        }
+
+        let _reserved = read_u32(&mut reader)?;
+        let _size = read_u32(&mut reader)?;
+        let _reserved = read_u32(&mut reader)?;
+        let files_offset = read_u32(&mut reader)?;
+        let _reserved = read_u32(&mut reader)?;
+        let _version = read_u16(&mut reader)?;
+        let nfolders = read_u16(&mut reader)?;
+        let nfiles = read_u16(&mut reader)?;
+        let flags = read_u16(&mut reader)?;
+        let _set_id = read_u16(&mut reader)?;
+        let _index = read_u16(&mut reader)?;
+
+        let (mut folder_reserve, mut data_reserve) = (0, 0);
+        if flags & FLAG_RESERVE_PRESENT != 0 {
+            let header_reserve = read_u16(&mut reader)?;
+            folder_reserve = read_u8(&mut reader)?;
+            data_reserve = read_u8(&mut reader)?;
+            skip(&mut reader, i64::from(header_reserve))?;
+        }
+
+        if flags & FLAG_PREV_CABINET != 0 {
+            read_string(&mut reader)?;
+            read_string(&mut reader)?;
+        }
+
+        if flags & FLAG_NEXT_CABINET != 0 {
+            read_string(&mut reader)?;
+            read_string(&mut reader)?;
+        }
+
+        let mut folders = Vec::with_capacity(nfolders as usize);
+        for _ in 0..nfolders {
+            let offset = read_u32(&mut reader)?;
+            let blocks = read_u16(&mut reader)?;
+            let compression = CabCompression::from(read_u16(&mut reader)?);
+            skip(&mut reader, i64::from(folder_reserve))?;
+            folders.push(Folder { compression, blocks, offset });
+        }
+
+        reader.seek(SeekFrom::Start(u64::from(files_offset))).map_err(CabError::Read)?;
+
+        let mut files = Vec::with_capacity(nfiles as usize);
+        for _ in 0..nfiles {
+            let size = read_u32(&mut reader)?;
+            let offset = read_u32(&mut reader)?;
+            let folder = read_u16(&mut reader)?;
+            let date = read_u16(&mut reader)?;
+            let time = read_u16(&mut reader)?;
+            let attributes = read_u16(&mut reader)?;
+            let name = read_string(&mut reader)?;
+
+            // Names without the UTF flag are in an unspecified code page, which is almost always
+            // ASCII in firmware archives.
+            let name = if attributes & ATTRIBUTE_NAME_IS_UTF != 0 {
+                String::from_utf8_lossy(&name).into()
+            } else {
+                name.iter().map(|&byte| byte as char).collect::<String>().into()
+            };
+
+            files.push(CabFile {
+                attributes,
+                date_time: (date, time),
+                name,
+                size,
+                folder,
+                offset,
+            });
+        }
+
+        Ok(Cabinet { reader, files, folders, data_reserve, cache: None })
+    }
+
+    /// The files which are stored in the cabinet.
+    pub fn files(&self) -> &[CabFile] { &self.files }
+
+    /// The file with the given name, which is compared case-insensitively.
+    pub fn file(&self, name: &str) -> Option<&CabFile> {
+        self.files.iter().find(|file| file.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Extracts the contents of the file with the given name.
+    pub fn extract(&mut self, name: &str) -> Result<Vec<u8>, CabError> {
+        let file = self.file(name).ok_or_else(|| CabError::NoFile(name.into()))?.clone();
+
+        if file.folder >= FOLDER_CONTINUED {
+            return Err(CabError::Continued(file.name));
+        }
+
+        let start = file.offset as usize;
+        let end = start + file.size as usize;
+        let data = self.folder(file.folder)?;
+        if data.len() < end {
+            return Err(CabError::InvalidData(file.folder, "the folder ends before the file"));
+        }
+
+        Ok(data[start..end].to_vec())
+    }
+
+    /// Parses every metainfo file in the cabinet, and combines their components.
+    pub fn metainfo(&mut self) -> Result<Metadata, CabError> {
+        let names = self.names(CabFile::is_metainfo);
+        if names.is_empty() {
+            return Err(CabError::NoMetainfo);
+        }
+
+        let mut metadata = Metadata::default();
+        for name in names {
+            let data = self.extract(&name)?;
+            let mut parsed = Metadata::from_xml(data.as_slice()).map_err(CabError::Metainfo)?;
+            metadata.components.append(&mut parsed.components);
+        }
+
+        Ok(metadata)
+    }
+
+    /// The name of the firmware payload.
+    ///
+    /// This is the file named by the content checksums of the metainfo, or else the only file
+    /// which is neither a metainfo file nor a signature.
+    pub fn payload_name(&mut self) -> Result<Box<str>, CabError> {
+        let metadata = self.metainfo()?;
+        self.payload_name_in(&metadata)
+    }
+
+    fn payload_name_in(&self, metadata: &Metadata) -> Result<Box<str>, CabError> {
+        let named = content_checksums(metadata)
+            .filter_map(|checksum| checksum.filename.as_ref())
+            .find_map(|filename| self.file(filename).map(|file| file.name.clone()));
+
+        if let Some(name) = named {
+            return Ok(name);
+        }
+
+        let mut payloads = self.names(|file| !file.is_metainfo() && !file.is_signature());
+        if payloads.len() == 1 {
+            Ok(payloads.remove(0))
+        } else {
+            Err(CabError::NoPayload)
+        }
+    }
+
+    /// Extracts the firmware payload.
+    pub fn payload(&mut self) -> Result<Vec<u8>, CabError> {
+        let name = self.payload_name()?;
+        self.extract(&name)
+    }
+
+    /// Extracts every detached signature, paired with its file name.
+    pub fn signatures(&mut self) -> Result<Vec<(Box<str>, Vec<u8>)>, CabError> {
+        let mut signatures = Vec::new();
+        for name in self.names(CabFile::is_signature) {
+            let data = self.extract(&name)?;
+            signatures.push((name, data));
+        }
+
+        Ok(signatures)
+    }
+
+    /// Validates the payload against the content checksums of the metainfo.
+    ///
+    /// Files which are named by a checksum are validated against it. A checksum without a file
+    /// name applies to the payload. Fails if the metainfo does not have any content checksums.
+    pub fn validate_payload(&mut self) -> Result<(), CabError> {
+        let metadata = self.metainfo()?;
+        self.validate_payload_with(&metadata)
+    }
+
+    /// Validates the payload against the content checksums of metainfo which was already parsed.
+    pub(crate) fn validate_payload_with(&mut self, metadata: &Metadata) -> Result<(), CabError> {
+        let payload = self.payload_name_in(metadata)?;
+
+        let mut validated = false;
+        for checksum in content_checksums(metadata) {
+            let name = checksum.filename.as_ref().map_or(&*payload, AsRef::as_ref);
+            let data = self.extract(name)?;
+            let expected = checksum.value.to_lowercase();
+            let algorithm = checksum_guess_kind(&expected);
+            let valid = validate_checksum(&mut data.as_slice(), &expected, algorithm)
+                .map_err(CabError::Read)?;
+
+            if !valid {
+                return Err(CabError::ChecksumMismatch(name.into(), checksum.kind.clone()));
+            }
+
+            validated = true;
+        }
+
+        if validated {
+            Ok(())
+        } else {
+            Err(CabError::NoChecksums)
+        }
+    }
+
+    fn names<F: Fn(&CabFile) -> bool>(&self, filter: F) -> Vec<Box<str>> {
+        self.files.iter().filter(|file| filter(file)).map(|file| file.name.clone()).collect()
+    }
+
+    /// The decompressed data of a folder, which is cached until another folder is read.
+    fn folder(&mut self, index: u16) -> Result<&[u8], CabError> {
+        if self.cache.as_ref().map_or(true, |&(cached, _)| cached != index) {
+            let data = self.decompress_folder(index)?;
+            self.cache = Some((index, data));
+        }
+
+        Ok(self.cache.as_ref().map(|(_, data)| data.as_slice()).unwrap_or_default())
+    }
+
+    /// Decompresses every data block of a folder.
+    fn decompress_folder(&mut self, index: u16) -> Result<Vec<u8>, CabError> {
+        let folder = self
+            .folders
+            .get(index as usize)
+            .ok_or(CabError::InvalidData(index, "the folder does not exist"))?
+            .clone();
+
+        match folder.compression {
+            CabCompression::None | CabCompression::MsZip => (),
+            other => return Err(CabError::UnsupportedCompression(index, other)),
+        }
+
+        self.reader.seek(SeekFrom::Start(u64::from(folder.offset))).map_err(CabError::Read)?;
+
+        let mut data = Vec::new();
+        for _ in 0..folder.blocks {
+            let _checksum = read_u32(&mut self.reader)?;
+            let compressed = read_u16(&mut self.reader)?;
+            let uncompressed = read_u16(&mut self.reader)?;
+            skip(&mut self.reader, i64::from(self.data_reserve))?;
+
+            let mut block = vec![0u8; compressed as usize];
+            self.reader.read_exact(&mut block).map_err(CabError::Read)?;
+
+            if folder.compression == CabCompression::None {
+                data.extend_from_slice(&block);
+                continue;
+            }
+
+            if !block.starts_with(MSZIP_MAGIC) {
+                return Err(CabError::InvalidData(index, "MSZIP block does not begin with CK"));
+            }
+
+            let inflated = inflate_block(&data, &block[MSZIP_MAGIC.len()..])?;
+            if inflated.len() != uncompressed as usize {
+                return Err(CabError::InvalidData(index, "MSZIP block has the wrong size"));
+            }
+
+            data.extend_from_slice(&inflated);
+        }
+
+        Ok(data)
+    }
+}
+
+/// The checksums of the contents of a cabinet, rather than of the cabinet itself.
+fn content_checksums(metadata: &Metadata) -> impl Iterator<Item = &Checksum> {
+    metadata
+        .components
+        .iter()
+        .flat_map(|component| component.releases.iter())
+        .flat_map(|release| release.checksums.iter())
+        .filter(|checksum| checksum.target.as_ref().map_or(false, |t| &**t == "content"))
+}
+
+/// Inflates an MSZIP block, which may refer back into the output of the preceding blocks.
+///
+/// Each block is a complete deflate stream, but shares its history window with the block before
+/// it. The window is supplied by prepending it to the stream as a stored deflate block, whose
+/// output is then discarded.
+fn inflate_block(history: &[u8], block: &[u8]) -> Result<Vec<u8>, CabError> {
+    let window = &history[history.len().saturating_sub(MSZIP_WINDOW)..];
+
+    let mut stream = Vec::with_capacity(5 + window.len() + block.len());
+    if !window.is_empty() {
+        let length = window.len() as u16;
+        stream.push(0x00);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(window);
+    }
+
+    stream.extend_from_slice(block);
+
+    let mut inflated = Vec::new();
+    DeflateDecoder::new(stream.as_slice())
+        .read_to_end(&mut inflated)
+        .map_err(CabError::Decompress)?;
+
+    Ok(inflated.split_off(window.len()))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, CabError> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer).map_err(CabError::Read)?;
+    Ok(buffer[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, CabError> {
+    let mut buffer = [0u8; 2];
+    reader.read_exact(&mut buffer).map_err(CabError::Read)?;
+    Ok(u16::from_le_bytes(buffer))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, CabError> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer).map_err(CabError::Read)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+/// Reads a null-terminated string.
+fn read_string<R: Read>(reader: &mut R) -> Result<Vec<u8>, CabError> {
+    let mut string = Vec::new();
+    loop {
+        match read_u8(reader)? {
+            0 => return Ok(string),
+            byte => string.push(byte),
+        }
+    }
+}
+
+fn skip<R: Seek>(reader: &mut R, bytes: i64) -> Result<(), CabError> {
+    if bytes != 0 {
+        reader.seek(SeekFrom::Current(bytes)).map_err(CabError::Read)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a cabinet with a single folder, which is split into blocks of 32 KiB.
+    ///
+    /// MSZIP blocks are cut from one deflate stream, as they are by Microsoft's tools, so that a
+    /// block may refer back into the blocks before it. Each block is flushed to a byte boundary
+    /// and closed with an empty final block, which makes it a complete deflate stream.
+    pub(crate) fn cabinet(mszip: bool, files: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+
+        fn put16(buffer: &mut Vec<u8>, value: u16) {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+
+        fn put32(buffer: &mut Vec<u8>, value: u32) {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut entries = Vec::new();
+        let mut stream = Vec::new();
+        for (name, data) in files {
+            put32(&mut entries, data.len() as u32);
+            put32(&mut entries, stream.len() as u32);
+            entries.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0x20, 0]);
+            entries.extend_from_slice(name.as_bytes());
+            entries.push(0);
+            stream.extend_from_slice(data);
+        }
+
+        let mut blocks = Vec::new();
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Default::default());
+        let chunks: Vec<&[u8]> = stream.chunks(32 * 1024).collect();
+        for chunk in &chunks {
+            let data = if mszip {
+                encoder.write_all(chunk).unwrap();
+                encoder.flush().unwrap();
+
+                let mut data = b"CK".to_vec();
+                data.append(encoder.get_mut());
+                data.extend_from_slice(&[0x03, 0x00]);
+                data
+            } else {
+                chunk.to_vec()
+            };
+
+            put32(&mut blocks, 0);
+            put16(&mut blocks, data.len() as u16);
+            put16(&mut blocks, chunk.len() as u16);
+            blocks.extend_from_slice(&data);
+        }
+
+        let data_offset = 36 + 8 + entries.len() as u32;
+        let mut cab = b"MSCF".to_vec();
+        for &value in &[0, data_offset + blocks.len() as u32, 0, 44, 0] {
+            put32(&mut cab, value);
+        }
+
+        for &value in &[0x0103, 1, files.len() as u16, 0, 0, 0] {
+            put16(&mut cab, value);
+        }
+
+        put32(&mut cab, data_offset);
+        put16(&mut cab, chunks.len() as u16);
+        put16(&mut cab, mszip as u16);
+        cab.extend_from_slice(&entries);
+        cab.extend_from_slice(&blocks);
+        cab
+    }
+
+    #[test]
+    fn cabinet_files() {
+        let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let metainfo = format!(
+            r#"<component type="firmware">
+  <id>com.example.Mock.firmware</id>
+  <releases>
+    <release version="1.2.4">
+      <checksum type="sha256" target="content" filename="mock.bin">{}</checksum>
+    </release>
+  </releases>
+</component>"#,
+            crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, &payload)
+        );
+
+        for &mszip in &[false, true] {
+            let files: &[(&str, &[u8])] = &[
+                ("mock.metainfo.xml", metainfo.as_bytes()),
+                ("mock.bin", &payload),
+                ("mock.bin.asc", b"-----BEGIN PGP SIGNATURE-----"),
+            ];
+
+            let mut cab = Cabinet::new(std::io::Cursor::new(cabinet(mszip, files))).unwrap();
+            assert_eq!(cab.files().len(), 3);
+            assert_eq!(&*cab.payload_name().unwrap(), "mock.bin");
+            assert_eq!(cab.payload().unwrap(), payload);
+            assert_eq!(&*cab.metainfo().unwrap().components[0].id, "com.example.Mock.firmware");
+            assert_eq!(&*cab.signatures().unwrap()[0].0, "mock.bin.asc");
+            assert!(cab.validate_payload().is_ok());
+        }
+
+        let files: &[(&str, &[u8])] =
+            &[("mock.metainfo.xml", metainfo.as_bytes()), ("mock.bin", b"tampered")];
+        let mut cab = Cabinet::new(std::io::Cursor::new(cabinet(true, files))).unwrap();
+        assert!(cab.validate_payload().is_err());
+
+        let metainfo = r#"<component><id>com.example.Mock.firmware</id></component>"#;
+        let files: &[(&str, &[u8])] =
+            &[("mock.metainfo.xml", metainfo.as_bytes()), ("mock.bin", b"unchecked")];
+        let mut cab = Cabinet::new(std::io::Cursor::new(cabinet(true, files))).unwrap();
+        match cab.validate_payload() {
+            Err(CabError::NoChecksums) => (),
+            other => panic!("expected no checksums, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cabinet_mszip_history() {
+        use std::io::Write;
+
+        // Noise does not compress by itself, so the second block can only be small if it refers
+        // back across the boundary into the noise of the first block.
+        let mut state = 0x2545_f491u32;
+        let noise: Vec<u8> = (0..20 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        let payload: Vec<u8> = noise.iter().cycle().take(3 * noise.len()).cloned().collect();
+        let data = cabinet(true, &[("mock.bin", &payload)]);
+        assert!(data.len() < 2 * noise.len(), "blocks were compressed independently");
+
+        let mut cab = Cabinet::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(cab.extract("mock.bin").unwrap(), payload);
+
+        // Without the history of the first block, the second cannot be inflated.
+        let (first, second) = payload.split_at(32 * 1024);
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(first).unwrap();
+        encoder.flush().unwrap();
+        encoder.get_mut().clear();
+        encoder.write_all(second).unwrap();
+        let block = encoder.finish().unwrap();
+
+        assert_eq!(inflate_block(first, &block).unwrap(), second);
+        assert!(inflate_block(&[], &block).map_or(true, |data| data != second));
+    }
+
+    #[test]
+    fn cabinet_malformed() {
+        let files: &[(&str, &[u8])] = &[("mock.bin", b"firmware payload")];
+        let extract = |data: Vec<u8>| -> Result<Vec<u8>, CabError> {
+            Cabinet::new(std::io::Cursor::new(data))?.extract("mock.bin")
+        };
+
+        let mut bad_magic = cabinet(true, files);
+        let ck = bad_magic.windows(2).position(|bytes| bytes == b"CK").unwrap();
+        bad_magic[ck] = b'X';
+        match extract(bad_magic) {
+            Err(CabError::InvalidData(0, "MSZIP block does not begin with CK")) => (),
+            other => panic!("expected a bad MSZIP block, found {:?}", other),
+        }
+
+        let mut truncated = cabinet(false, files);
+        truncated.truncate(truncated.len() - 4);
+        match extract(truncated) {
+            Err(CabError::Read(_)) => (),
+            other => panic!("expected the folder to be truncated, found {:?}", other),
+        }
+
+        // The offset of the first file entry, which follows the header and the folder entry.
+        let mut past_folder = cabinet(false, files);
+        past_folder[48..52].copy_from_slice(&1024u32.to_le_bytes());
+        match extract(past_folder) {
+            Err(CabError::InvalidData(0, "the folder ends before the file")) => (),
+            other => panic!("expected the file to be past the folder, found {:?}", other),
+        }
+    }
+}
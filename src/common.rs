@@ -1,11 +1,15 @@
 use crypto_hash::{Algorithm, Hasher};
 use hex_view::HexView;
 use std::{
-    io::{self, Read},
+    fs,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
 };
 use url::Url;
 
+/// The magic bytes at the beginning of a gzip stream.
+pub const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
 /// Based on libfwupd/fwupd-common.c
 pub fn checksum_guess_kind(checksum: &str) -> Algorithm {
     match checksum.len() {
@@ -39,11 +43,46 @@ pub fn validate_checksum<R: Read>(
 ) -> io::Result<bool> {
     let mut hasher = Hasher::new(alg);
     io::copy(data, &mut hasher)?;
-    let digest = format!("{:x}", HexView::from(hasher.finish().as_slice()));
-    Ok(checksum == digest.as_str())
+    Ok(checksum == hex_digest(hasher).as_str())
+}
+
+fn hex_digest(mut hasher: Hasher) -> String {
+    format!("{:x}", HexView::from(hasher.finish().as_slice()))
+}
+
+/// Hashes the bytes that are written to the inner writer, so that a file which is being
+/// written does not have to be read back to validate its checksum.
+pub struct HashWriter<W> {
+    inner:  W,
+    hasher: Hasher,
+}
+
+impl<W: Write> HashWriter<W> {
+    /// Continues from a hasher which has already hashed the preceding bytes of the file.
+    pub fn with_hasher(inner: W, hasher: Hasher) -> Self { Self { inner, hasher } }
+
+    /// The hex digest of every byte that has been hashed.
+    pub fn finish(self) -> String { hex_digest(self.hasher) }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.write_all(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+/// The directory that the client caches firmware and remote metadata in, for the current user.
+pub fn cache_dir() -> PathBuf {
+    xdg::BaseDirectories::with_prefix("fwupd-client")
+        .expect("failed to get XDG base directories")
+        .get_cache_home()
 }
 
-pub fn cache_path_from_uri(uri: &Url) -> PathBuf {
+pub fn cache_path_from_uri(cache_dir: &Path, uri: &Url) -> PathBuf {
     let domain = uri.host_str();
 
     let path = Path::new(uri.path())
@@ -53,7 +92,7 @@ pub fn cache_path_from_uri(uri: &Url) -> PathBuf {
         .expect("URI is not UTF-8");
 
     let maybe_heap: String;
-    cache_path(match domain.as_ref() {
+    cache_path(cache_dir, match domain.as_ref() {
         Some(domain) => {
             maybe_heap = [domain, "/", path].concat();
             Path::new(&maybe_heap)
@@ -62,14 +101,28 @@ pub fn cache_path_from_uri(uri: &Url) -> PathBuf {
     })
 }
 
-pub fn cache_path(file: &Path) -> PathBuf {
-    xdg::BaseDirectories::with_prefix("fwupd-client")
-        .expect("failed to get XDG base directories")
-        .place_cache_file(file)
-        .unwrap_or_else(|why| panic!("failed to place {:?} in cache: {}", file, why))
+/// The path of a file within the cache directory, whose parent directories are created.
+pub fn cache_path(cache_dir: &Path, file: &Path) -> PathBuf {
+    let path = cache_dir.join(file);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|why| panic!("failed to place {:?} in cache: {}", file, why));
+    }
+
+    path
+}
+
+/// Creates an empty directory for a test, which is unique to the process.
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fwupd-dbus-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
 }
 
 pub const KEY_APPSTREAM_ID: &str = "AppstreamId"; // s
+pub const KEY_BRANCH: &str = "Branch"; // s
 pub const KEY_CATEGORIES: &str = "Categories"; // as
 pub const KEY_CHECKSUM: &str = "Checksum"; // as
 pub const KEY_CREATED: &str = "Created"; // t
@@ -81,24 +134,30 @@ pub const KEY_FLAGS: &str = "Flags"; // t
 pub const KEY_FLASHES_LEFT: &str = "FlashesLeft"; // u
 pub const KEY_GUID: &str = "Guid"; // as
 pub const KEY_HOMEPAGE: &str = "Homepage"; // s
+pub const KEY_HSI_LEVEL: &str = "HsiLevel"; // u
+pub const KEY_HSI_RESULT: &str = "HsiResult"; // u
 pub const KEY_ICON: &str = "Icon"; // as
 pub const KEY_INSTALL_DURATION: &str = "InstallDuration"; // u
 pub const KEY_INSTANCE_IDS: &str = "InstanceIds"; // as
 pub const KEY_LICENSE: &str = "License"; // s
+pub const KEY_LOCATIONS: &str = "Locations"; // as
 pub const KEY_METADATA: &str = "Metadata"; // a{ss}
 pub const KEY_MODIFIED: &str = "Modified"; // t
 pub const KEY_NAME: &str = "Name"; // s
+pub const KEY_OBSOLETES: &str = "Obsoletes"; // as
 pub const KEY_PARENT_DEVICE_ID: &str = "ParentDeviceId"; // s
 pub const KEY_PLUGIN: &str = "Plugin"; // s
 pub const KEY_PROTOCOL: &str = "Protocol"; // s
 pub const KEY_RELEASE: &str = "Release"; // a{sv}
 pub const KEY_REMOTE_ID: &str = "RemoteId"; // s
+pub const KEY_REQUEST_KIND: &str = "RequestKind"; // u
 pub const KEY_SERIAL: &str = "Serial"; // s
 pub const KEY_SIZE: &str = "Size"; // t
 pub const KEY_SOURCE_URL: &str = "SourceUrl"; // s
 pub const KEY_SUMMARY: &str = "Summary"; // s
 pub const KEY_TRUST_FLAGS: &str = "TrustFlags"; // t
 pub const KEY_UPDATE_ERROR: &str = "UpdateError"; // s
+pub const KEY_UPDATE_IMAGE: &str = "UpdateImage"; // s
 pub const KEY_UPDATE_MESSAGE: &str = "UpdateMessage"; // s
 pub const KEY_UPDATE_STATE: &str = "UpdateState"; // u
 pub const KEY_URI: &str = "Uri"; // s
@@ -1,19 +1,70 @@
-use dbus::arg::RefArg;
+use dbus::arg::{ArgType, RefArg};
 
-pub fn dbus_str<'a>(variant: &'a dyn RefArg, kind: &str) -> &'a str {
-    variant
-        .as_str()
-        .unwrap_or_else(|| panic!("expected str for {}, found {}", kind, variant.signature()))
+/// An error that occurs when a value received from the daemon does not have the expected type.
+#[derive(Debug, Error)]
+#[error(display = "expected {} for {}, found {}", expected, field, found)]
+pub struct DecodeError {
+    /// The key of the value which failed to decode.
+    pub field: Box<str>,
+    /// The DBus signatures that would have been accepted.
+    pub expected: &'static str,
+    /// The DBus signature that was received.
+    pub found: Box<str>,
+}
+
+impl DecodeError {
+    pub(crate) fn new(field: &str, expected: &'static str, variant: &dyn RefArg) -> Self {
+        DecodeError { field: field.into(), expected, found: (&*variant.signature()).into() }
+    }
 }
 
-pub fn dbus_u64(variant: &dyn RefArg, kind: &str) -> u64 {
+/// The signatures which are accepted as a string.
+const STRING: &str = "one of s, o or g";
+
+/// The signatures which are accepted as an unsigned integer.
+const UNSIGNED: &str = "one of b, y, q, u or t";
+
+/// The signatures which are accepted as a signed integer.
+const SIGNED: &str = "one of b, y, n, q, i, u or x";
+
+/// Values of an `a{sv}` dictionary may be wrapped in a variant.
+fn unwrap_variant(variant: &dyn RefArg) -> &dyn RefArg {
+    if variant.arg_type() == ArgType::Variant {
+        if let Some(inner) = variant.as_iter().and_then(|mut iter| iter.next()) {
+            return inner;
+        }
+    }
+
     variant
-        .as_u64()
-        .unwrap_or_else(|| panic!("expected u64 for {}, found {}", kind, variant.signature()))
 }
 
-pub fn dbus_i64(variant: &dyn RefArg, kind: &str) -> i64 {
+pub fn dbus_str<'a>(variant: &'a dyn RefArg, field: &str) -> Result<&'a str, DecodeError> {
+    let variant = unwrap_variant(variant);
+    variant.as_str().ok_or_else(|| DecodeError::new(field, STRING, variant))
+}
+
+pub fn dbus_u64(variant: &dyn RefArg, field: &str) -> Result<u64, DecodeError> {
+    let variant = unwrap_variant(variant);
+    variant.as_u64().ok_or_else(|| DecodeError::new(field, UNSIGNED, variant))
+}
+
+pub fn dbus_i64(variant: &dyn RefArg, field: &str) -> Result<i64, DecodeError> {
+    let variant = unwrap_variant(variant);
+    variant.as_i64().ok_or_else(|| DecodeError::new(field, SIGNED, variant))
+}
+
+pub fn dbus_str_array(variant: &dyn RefArg, field: &str) -> Result<Box<[Box<str>]>, DecodeError> {
+    let variant = unwrap_variant(variant);
+    if variant.arg_type() != ArgType::Array {
+        return Err(DecodeError::new(field, "as", variant));
+    }
+
     variant
-        .as_i64()
-        .unwrap_or_else(|| panic!("expected i64 for {}, found {}", kind, variant.signature()))
+        .as_iter()
+        .ok_or_else(|| DecodeError::new(field, "as", variant))?
+        .map(|elem| {
+            elem.as_str().map(Box::from).ok_or_else(|| DecodeError::new(field, "as", variant))
+        })
+        .collect::<Result<Vec<Box<str>>, DecodeError>>()
+        .map(Vec::into_boxed_slice)
 }
@@ -1,6 +1,6 @@
-use crate::{common::*, dbus_helpers::*, DBusEntry};
+use crate::{common::*, dbus_helpers::*, version, DBusEntries, Value, Version};
 use dbus::arg::RefArg;
-use std::iter::FromIterator;
+use std::{collections::HashMap, convert::TryFrom};
 
 bitflags! {
     /// Describes attributes of a device.
@@ -97,6 +97,10 @@ pub enum VersionFormat {
     IntelMe2,
 }
 
+impl Default for VersionFormat {
+    fn default() -> Self { VersionFormat::Unknown }
+}
+
 impl From<u8> for VersionFormat {
     fn from(value: u8) -> Self {
         use self::VersionFormat::*;
@@ -117,15 +121,23 @@ impl From<u8> for VersionFormat {
 
 /// The remote ID of a device.
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Shrinkwrap)]
-pub struct DeviceId(Box<str>);
+pub struct DeviceId(pub(crate) Box<str>);
+
+impl<'a> From<&'a str> for DeviceId {
+    fn from(id: &'a str) -> Self { DeviceId(id.into()) }
+}
 
 /// A device that is potentially-supported by fwupd.
 #[derive(Clone, Debug, Default)]
 pub struct Device {
+    /// The branch of the installed firmware, or `None` for the default branch.
+    pub branch:             Option<Box<str>>,
     pub checksum:           Option<Box<str>>,
     pub created:            u64,
     pub description:        Option<Box<str>>,
     pub device_id:          DeviceId,
+    /// Keys from the daemon which are not recognized by this crate.
+    pub extra:              HashMap<Box<str>, Value>,
     pub flags:              DeviceFlags,
     pub flashes_left:       Option<u32>,
     pub guid:               Box<[Box<str>]>,
@@ -167,87 +179,75 @@ impl Device {
 
     /// Check if the device must be updated offline.
     pub fn only_offline(&self) -> bool { self.has_flag(DeviceFlags::ONLY_OFFLINE) }
+
+    /// Parses a version, such as that of a release, with the version format of this device.
+    pub fn parse_version(&self, version: &str) -> Version {
+        Version::new(version, self.version_format.unwrap_or(version::DEFAULT_FORMAT))
+    }
+
+    /// The current version of the device, which is compared as libfwupd does.
+    pub fn parsed_version(&self) -> Version { self.parse_version(&self.version) }
 }
 
 impl AsRef<DeviceId> for Device {
     fn as_ref(&self) -> &DeviceId { &self.device_id }
 }
 
-impl FromIterator<DBusEntry> for Device {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusEntries> for Device {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
         let mut device = Device::default();
 
-        for (key, value) in iter {
+        for (key, value) in entries {
             let key = key.as_str();
             match key {
-                KEY_CHECKSUM => device.checksum = Some(dbus_str(&value, key).into()),
-                KEY_CREATED => device.created = dbus_u64(&value, key),
-                KEY_DESCRIPTION => device.description = Some(dbus_str(&value, key).into()),
-                KEY_DEVICE_ID => device.device_id = DeviceId(dbus_str(&value, key).into()),
-                KEY_FLAGS => device.flags = DeviceFlags::from_bits_truncate(dbus_u64(&value, key)),
-                KEY_FLASHES_LEFT => device.flashes_left = Some(dbus_u64(&value, key) as u32),
-                KEY_GUID => {
-                    device.guid = value
-                        .as_iter()
-                        .expect("Guid is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Guid is not an iterator"))
-                        .map(|elem| dbus_str(elem, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
-                KEY_ICON => {
-                    device.icon = value
-                        .as_iter()
-                        .expect("Icon is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Icon is not an iterator"))
-                        .map(|elem| dbus_str(elem, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
+                KEY_BRANCH => device.branch = Some(dbus_str(&value, key)?.into()),
+                KEY_CHECKSUM => device.checksum = Some(dbus_str(&value, key)?.into()),
+                KEY_CREATED => device.created = dbus_u64(&value, key)?,
+                KEY_DESCRIPTION => device.description = Some(dbus_str(&value, key)?.into()),
+                KEY_DEVICE_ID => device.device_id = DeviceId(dbus_str(&value, key)?.into()),
+                KEY_FLAGS => {
+                    device.flags = DeviceFlags::from_bits_truncate(dbus_u64(&value, key)?)
                 }
+                KEY_FLASHES_LEFT => device.flashes_left = Some(dbus_u64(&value, key)? as u32),
+                KEY_GUID => device.guid = dbus_str_array(&value, key)?,
+                KEY_ICON => device.icon = dbus_str_array(&value, key)?,
                 KEY_INSTALL_DURATION => {
-                    device.install_duration = Some(dbus_u64(&value, key) as u32)
-                }
-                KEY_INSTANCE_IDS => {
-                    device.instance_ids = value
-                        .as_iter()
-                        .expect("InstanceIds is not a variant")
-                        .flat_map(|array| array.as_iter().expect("InstanceIds is not an iterator"))
-                        .map(|value| dbus_str(value, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
+                    device.install_duration = Some(dbus_u64(&value, key)? as u32)
                 }
-                KEY_MODIFIED => device.modified = Some(dbus_u64(&value, key)),
-                KEY_NAME => device.name = dbus_str(&value, key).into(),
+                KEY_INSTANCE_IDS => device.instance_ids = dbus_str_array(&value, key)?,
+                KEY_MODIFIED => device.modified = Some(dbus_u64(&value, key)?),
+                KEY_NAME => device.name = dbus_str(&value, key)?.into(),
                 KEY_PARENT_DEVICE_ID => {
-                    device.parent_device_id = Some(DeviceId(dbus_str(&value, key).into()))
+                    device.parent_device_id = Some(DeviceId(dbus_str(&value, key)?.into()))
                 }
-                KEY_PLUGIN => device.plugin = dbus_str(&value, key).into(),
-                KEY_SERIAL => device.serial = Some(dbus_str(&value, key).into()),
-                KEY_SUMMARY => device.summary = Some(dbus_str(&value, key).into()),
-                KEY_UPDATE_ERROR => device.update_error = Some(dbus_str(&value, key).into()),
-                KEY_UPDATE_MESSAGE => device.update_message = Some(dbus_str(&value, key).into()),
+                KEY_PLUGIN => device.plugin = dbus_str(&value, key)?.into(),
+                KEY_SERIAL => device.serial = Some(dbus_str(&value, key)?.into()),
+                KEY_SUMMARY => device.summary = Some(dbus_str(&value, key)?.into()),
+                KEY_UPDATE_ERROR => device.update_error = Some(dbus_str(&value, key)?.into()),
+                KEY_UPDATE_MESSAGE => device.update_message = Some(dbus_str(&value, key)?.into()),
                 KEY_UPDATE_STATE => {
-                    device.update_state = Some(UpdateState::from(dbus_u64(&value, key) as u8))
+                    device.update_state = Some(UpdateState::from(dbus_u64(&value, key)? as u8))
                 }
-                KEY_VENDOR => device.vendor = dbus_str(&value, key).into(),
-                KEY_VENDOR_ID => device.vendor_id = dbus_str(&value, key).into(),
-                KEY_VERSION => device.version = dbus_str(&value, key).into(),
+                KEY_VENDOR => device.vendor = dbus_str(&value, key)?.into(),
+                KEY_VENDOR_ID => device.vendor_id = dbus_str(&value, key)?.into(),
+                KEY_VERSION => device.version = dbus_str(&value, key)?.into(),
                 KEY_VERSION_BOOTLOADER => {
-                    device.version_bootloader = Some(dbus_str(&value, key).into())
+                    device.version_bootloader = Some(dbus_str(&value, key)?.into())
                 }
-                KEY_VERSION_LOWEST => device.version_lowest = Some(dbus_str(&value, key).into()),
+                KEY_VERSION_LOWEST => device.version_lowest = Some(dbus_str(&value, key)?.into()),
                 "VersionFormat" => {
-                    device.version_format = Some(VersionFormat::from(dbus_u64(&value, key) as u8))
+                    device.version_format =
+                        Some(VersionFormat::from(dbus_u64(&value, key)? as u8))
                 }
                 other => {
-                    eprintln!("unknown device key: {} ({}): {:?}", other, value.signature(), value,);
+                    debug!("unknown device key: {} ({}): {:?}", other, value.signature(), value);
+                    device.extra.insert(other.into(), Value::from(&*value.0));
                 }
             }
         }
 
-        device
+        Ok(device)
     }
 }
@@ -0,0 +1,463 @@
+//! Builds a remote from a directory of cabinet archives, so that firmware may be served to
+//! machines which cannot reach the LVFS.
+
+use crate::{AppStreamError, CabError, Cabinet, Metadata};
+use crypto_hash::{hex_digest, Algorithm};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use url::Url;
+use xml::{
+    attribute::OwnedAttribute,
+    reader::{self, EventReader},
+    writer::{self, EmitterConfig, EventWriter},
+};
+
+/// The directory that fwupd reads the configuration of remotes from.
+pub const REMOTES_DIR: &str = "/etc/fwupd/remotes.d";
+
+/// The name of the metadata which is written to the directory of the remote.
+pub const DIRECTORY_METADATA: &str = "firmware.xml.gz";
+
+/// An error that may occur when building a directory remote.
+#[derive(Debug, Error)]
+pub enum DirectoryError {
+    #[error(display = "unable to read the cabinet archive at {:?}", _0)]
+    Cabinet(PathBuf, #[error(cause, no_from)] CabError),
+    #[error(display = "unable to resolve the absolute path of {:?}", _1)]
+    Canonicalize(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "failed to create {:?}", _1)]
+    Create(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "the remote ID {:?} is not a valid file name", _0)]
+    InvalidRemoteId(Box<str>),
+    #[error(display = "failed to generate the metadata of the remote")]
+    Metadata(#[error(cause, no_from)] AppStreamError),
+    #[error(display = "failed to read {:?}", _1)]
+    Read(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "unable to convert {:?} into a file URI", _0)]
+    Uri(PathBuf),
+    #[error(display = "failed to write {:?}", _1)]
+    Write(#[error(cause, no_from)] io::Error, PathBuf),
+}
+
+/// Scans a directory of cabinet archives, and writes the metadata which describes them.
+///
+/// ```no_run
+/// use fwupd_dbus::{DirectoryRemoteBuilder, REMOTES_DIR};
+///
+/// let remote = DirectoryRemoteBuilder::new("/srv/firmware")
+///     .remote_id("offline")
+///     .title("Offline Firmware")
+///     .build()
+///     .unwrap();
+///
+/// remote.write_config(REMOTES_DIR).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DirectoryRemoteBuilder {
+    pub(crate) directory: PathBuf,
+    pub(crate) remote_id: Option<Box<str>>,
+    pub(crate) title:     Option<Box<str>>,
+}
+
+impl DirectoryRemoteBuilder {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self { directory: directory.into(), remote_id: None, title: None }
+    }
+
+    /// The ID of the remote, which is the name of the directory by default.
+    ///
+    /// As the configuration is written to a file of the same name, the ID may not contain `/`
+    /// or begin with `.`.
+    pub fn remote_id(mut self, remote_id: &str) -> Self {
+        self.remote_id = Some(remote_id.into());
+        self
+    }
+
+    /// The title of the remote, which is its ID by default.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Scans the cabinet archives of the directory, and writes their metadata into it.
+    ///
+    /// The metainfo of each cabinet is copied into the metadata as it is, except for the
+    /// locations, container checksums and download size of its releases, which are replaced by
+    /// those of the cabinet in the directory.
+    pub fn build(self) -> Result<DirectoryRemote, DirectoryError> {
+        let directory = fs::canonicalize(&self.directory)
+            .map_err(|why| DirectoryError::Canonicalize(why, self.directory.clone()))?;
+
+        let remote_id: Box<str> = match self.remote_id {
+            Some(remote_id) => remote_id,
+            None => directory
+                .file_name()
+                .map_or_else(|| "directory".into(), |name| name.to_string_lossy().into()),
+        };
+
+        validate_remote_id(&remote_id)?;
+
+        let uri = Url::from_directory_path(&directory)
+            .map_err(|()| DirectoryError::Uri(directory.clone()))?;
+
+        let cabinets = cabinets(&directory)?;
+
+        let mut xml = Vec::new();
+        {
+            let mut writer = EmitterConfig::new().create_writer(&mut xml);
+            let error = |why| DirectoryError::Metadata(AppStreamError::Write(why));
+
+            writer
+                .write(writer::XmlEvent::start_element("components").attr("version", "0.9"))
+                .map_err(error)?;
+
+            for path in &cabinets {
+                let cabinet = DirectoryCabinet::read(path)?;
+                for metainfo in &cabinet.metainfo {
+                    copy_metainfo(&mut writer, metainfo, &cabinet).map_err(|why| {
+                        DirectoryError::Cabinet(path.clone(), CabError::Metainfo(why))
+                    })?;
+                }
+            }
+
+            writer.write(writer::XmlEvent::end_element()).map_err(error)?;
+        }
+
+        let metadata = Metadata::from_xml(xml.as_slice()).map_err(DirectoryError::Metadata)?;
+        let metadata_path = directory.join(DIRECTORY_METADATA);
+        write_metadata(&xml, &metadata_path)?;
+
+        let title = self.title.unwrap_or_else(|| remote_id.clone());
+
+        Ok(DirectoryRemote { cabinets, metadata, metadata_path, remote_id, title, uri })
+    }
+}
+
+/// A directory of cabinet archives, whose metadata has been written.
+#[derive(Clone, Debug)]
+pub struct DirectoryRemote {
+    /// The cabinet archives which were found in the directory.
+    pub cabinets:      Vec<PathBuf>,
+    pub metadata:      Metadata,
+    pub metadata_path: PathBuf,
+    pub remote_id:     Box<str>,
+    pub title:         Box<str>,
+    /// The URI of the directory, which the configuration points fwupd at.
+    pub uri:           Url,
+}
+
+impl DirectoryRemote {
+    /// The configuration of the remote, in the format of the `remotes.d` directory.
+    ///
+    /// fwupd only treats a remote as a directory remote if its `MetadataURI` is a directory. A
+    /// file would make it a local remote instead, whose locations are relative to that file.
+    pub fn config(&self) -> String {
+        format!(
+            "[fwupd Remote]\nEnabled=true\nTitle={}\nKeyring=none\nMetadataURI={}\n",
+            self.title, self.uri
+        )
+    }
+
+    /// Writes the configuration of the remote into a `remotes.d` directory, such as
+    /// `REMOTES_DIR`, and returns the path of the file which was written.
+    pub fn write_config<P: AsRef<Path>>(&self, remotes_dir: P) -> Result<PathBuf, DirectoryError> {
+        validate_remote_id(&self.remote_id)?;
+
+        let path = remotes_dir.as_ref().join([&*self.remote_id, ".conf"].concat());
+        fs::write(&path, self.config()).map_err(|why| DirectoryError::Write(why, path.clone()))?;
+        Ok(path)
+    }
+}
+
+/// A cabinet archive of the directory, and the metainfo which describes its releases.
+struct DirectoryCabinet {
+    checksums: Vec<(&'static str, String)>,
+    filename:  Box<str>,
+    location:  Url,
+    /// Every metainfo file of the cabinet, exactly as they are stored in it.
+    metainfo:  Vec<Vec<u8>>,
+    size:      u64,
+}
+
+impl DirectoryCabinet {
+    /// Reads the cabinet, after validating its payload against the checksums of its metainfo.
+    fn read(path: &Path) -> Result<Self, DirectoryError> {
+        let data = fs::read(path).map_err(|why| DirectoryError::Read(why, path.to_path_buf()))?;
+        let cabinet_error = |why| DirectoryError::Cabinet(path.to_path_buf(), why);
+
+        let mut cabinet = Cabinet::new(io::Cursor::new(data.as_slice())).map_err(cabinet_error)?;
+        let metadata = cabinet.metainfo().map_err(cabinet_error)?;
+        cabinet.validate_payload_with(&metadata).map_err(cabinet_error)?;
+
+        let names: Vec<Box<str>> = cabinet
+            .files()
+            .iter()
+            .filter(|file| file.is_metainfo())
+            .map(|file| file.name.clone())
+            .collect();
+
+        let metainfo = names
+            .iter()
+            .map(|name| cabinet.extract(name))
+            .collect::<Result<_, _>>()
+            .map_err(cabinet_error)?;
+
+        let location =
+            Url::from_file_path(path).map_err(|()| DirectoryError::Uri(path.to_path_buf()))?;
+
+        Ok(DirectoryCabinet {
+            checksums: vec![
+                ("sha1", hex_digest(Algorithm::SHA1, &data)),
+                ("sha256", hex_digest(Algorithm::SHA256, &data)),
+            ],
+            filename: path.file_name().unwrap_or_default().to_string_lossy().into(),
+            location,
+            metainfo,
+            size: data.len() as u64,
+        })
+    }
+}
+
+/// Rejects IDs which would write the configuration outside of the `remotes.d` directory.
+fn validate_remote_id(remote_id: &str) -> Result<(), DirectoryError> {
+    if remote_id.is_empty() || remote_id.contains('/') || remote_id.starts_with('.') {
+        return Err(DirectoryError::InvalidRemoteId(remote_id.into()));
+    }
+
+    Ok(())
+}
+
+/// The cabinet archives of the directory, sorted by their file names.
+fn cabinets(directory: &Path) -> Result<Vec<PathBuf>, DirectoryError> {
+    let error = |why| DirectoryError::Read(why, directory.to_path_buf());
+
+    let mut cabinets = Vec::new();
+    for entry in fs::read_dir(directory).map_err(error)? {
+        let path = entry.map_err(error)?.path();
+        let is_cab = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("cab"));
+        if is_cab && path.is_file() {
+            cabinets.push(path);
+        }
+    }
+
+    cabinets.sort();
+    Ok(cabinets)
+}
+
+/// Copies the components of a metainfo file into the metadata, giving each release the
+/// location, container checksums and download size of the cabinet in place of its own.
+///
+/// Everything else is copied event by event, so that nothing which fwupd reads is lost.
+fn copy_metainfo<W: Write>(
+    writer: &mut EventWriter<W>,
+    metainfo: &[u8],
+    cabinet: &DirectoryCabinet,
+) -> Result<(), AppStreamError> {
+    use self::reader::XmlEvent;
+
+    // The names of the elements which are open.
+    let mut path: Vec<String> = Vec::new();
+    // The depth within an element which is being replaced.
+    let mut skipping = 0;
+
+    for event in EventReader::new(metainfo) {
+        let event = event.map_err(AppStreamError::Xml)?;
+
+        if skipping != 0 {
+            match event {
+                XmlEvent::StartElement { .. } => skipping += 1,
+                XmlEvent::EndElement { .. } => skipping -= 1,
+                _ => (),
+            }
+
+            continue;
+        }
+
+        match event {
+            XmlEvent::StartDocument { .. } => continue,
+            XmlEvent::StartElement { ref name, ref attributes, .. } => {
+                if is_release(&path) && is_replaced(&name.local_name, attributes) {
+                    skipping = 1;
+                    continue;
+                }
+
+                path.push(name.local_name.clone());
+
+                // The components of a `<components>` root join those of the metadata.
+                if path.len() == 1 && name.local_name == "components" {
+                    continue;
+                }
+            }
+            XmlEvent::EndElement { ref name } => {
+                if is_release(&path) {
+                    write_cabinet(writer, cabinet).map_err(AppStreamError::Write)?;
+                }
+
+                path.pop();
+
+                if path.is_empty() && name.local_name == "components" {
+                    continue;
+                }
+            }
+            _ => (),
+        }
+
+        if let Some(event) = event.as_writer_event() {
+            writer.write(event).map_err(AppStreamError::Write)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// If the innermost open element is a release of a component.
+fn is_release(path: &[String]) -> bool {
+    let len = path.len();
+    len >= 2 && path[len - 2] == "releases" && path[len - 1] == "release"
+}
+
+/// If the child of a release describes the cabinet, rather than the firmware within it.
+fn is_replaced(name: &str, attributes: &[OwnedAttribute]) -> bool {
+    let attribute = |key: &str| {
+        attributes.iter().find(|attr| attr.name.local_name == key).map(|attr| attr.value.as_str())
+    };
+
+    match name {
+        "location" => true,
+        "checksum" => attribute("target") != Some("content"),
+        "size" => attribute("type") == Some("download"),
+        _ => false,
+    }
+}
+
+/// Writes the location, container checksums and download size of the cabinet into a release.
+fn write_cabinet<W: Write>(
+    writer: &mut EventWriter<W>,
+    cabinet: &DirectoryCabinet,
+) -> Result<(), writer::Error> {
+    text_element(writer, "location", &[], cabinet.location.as_str())?;
+
+    for (kind, value) in &cabinet.checksums {
+        let filename = &*cabinet.filename;
+        let attributes = [("type", *kind), ("filename", filename), ("target", "container")];
+        text_element(writer, "checksum", &attributes, value)?;
+    }
+
+    text_element(writer, "size", &[("type", "download")], &cabinet.size.to_string())
+}
+
+fn text_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, &str)],
+    text: &str,
+) -> Result<(), writer::Error> {
+    let mut element = writer::XmlEvent::start_element(name);
+    for &(key, value) in attributes {
+        element = element.attr(key, value);
+    }
+
+    writer.write(element)?;
+    writer.write(writer::XmlEvent::characters(text))?;
+    writer.write(writer::XmlEvent::end_element())
+}
+
+fn write_metadata(xml: &[u8], path: &Path) -> Result<(), DirectoryError> {
+    let file = File::create(path).map_err(|why| DirectoryError::Create(why, path.to_path_buf()))?;
+
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    encoder
+        .write_all(xml)
+        .and_then(|()| encoder.finish())
+        .and_then(|mut file| file.flush())
+        .map_err(|why| DirectoryError::Write(why, path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cab::tests::cabinet, common::temp_dir};
+
+    #[test]
+    fn directory_remote() {
+        let directory = temp_dir("directory-remote");
+
+        let metainfo = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="firmware">
+  <id>com.example.Mock.firmware</id>
+  <name>Mock</name>
+  <provides><firmware type="flashed">2082b5e0-7a64-478a-b1b2-e3404fab6dad</firmware></provides>
+  <requires><firmware compare="ge" version="1.0.0" depth="1">bootloader</firmware></requires>
+  <custom><value key="LVFS::VersionFormat">triplet</value></custom>
+  <releases>
+    <release version="1.2.4" timestamp="1587945600">
+      <location>https://example.com/mock-1.2.4.cab</location>
+      <checksum type="sha1" target="container">0000</checksum>
+      <checksum type="sha256" target="content" filename="mock.bin">{}</checksum>
+      <description><p>Fixes a bug.</p><ul><li>Faster</li></ul></description>
+      <size type="installed">8</size>
+      <size type="download">1</size>
+    </release>
+  </releases>
+</component>"#,
+            crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, b"firmware")
+        );
+
+        let files: &[(&str, &[u8])] =
+            &[("mock.metainfo.xml", metainfo.as_bytes()), ("mock.bin", b"firmware")];
+        let cab = cabinet(true, files);
+        std::fs::write(directory.join("mock-1.2.4.cab"), &cab).unwrap();
+
+        let remote = DirectoryRemoteBuilder::new(&directory).remote_id("offline").build().unwrap();
+        assert_eq!(remote.cabinets.len(), 1);
+
+        let metadata = Metadata::open(&remote.metadata_path).unwrap();
+        let releases = metadata.releases_for_guid("2082b5e0-7a64-478a-b1b2-e3404fab6dad");
+        assert_eq!(releases.len(), 1);
+
+        let release = releases[0].1;
+        let location = Url::from_file_path(&remote.cabinets[0]).unwrap();
+        assert_eq!(release.locations.len(), 1);
+        assert_eq!(&*release.locations[0], location.as_str());
+        assert_eq!(release.size, cab.len() as u64);
+
+        let sha256 = crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, &cab);
+        let container: Vec<&str> =
+            release.container_checksums().map(|checksum| checksum.value.as_ref()).collect();
+        assert_eq!(container.len(), 2);
+        assert!(container.contains(&sha256.as_str()));
+        assert_eq!(release.checksums.len(), 3);
+
+        // Everything which does not describe the cabinet is copied from the metainfo.
+        let mut xml = String::new();
+        let file = std::fs::File::open(&remote.metadata_path).unwrap();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut xml).unwrap();
+        assert!(xml.contains(r#"<value key="LVFS::VersionFormat">triplet</value>"#));
+        assert!(xml.contains(r#"depth="1""#));
+        assert!(xml.contains("<ul><li>Faster</li></ul>"));
+        assert!(xml.contains(r#"<size type="installed">8</size>"#));
+
+        let config = remote.write_config(&directory).unwrap();
+        let config = std::fs::read_to_string(config).unwrap();
+        assert!(config.contains("Keyring=none"));
+        let uri = Url::from_directory_path(&remote.metadata_path.parent().unwrap()).unwrap();
+        assert!(config.contains(&format!("MetadataURI={}\n", uri)));
+
+        for remote_id in &["../offline", "offline/remote", ".offline", ""] {
+            match DirectoryRemoteBuilder::new(&directory).remote_id(remote_id).build() {
+                Err(DirectoryError::InvalidRemoteId(_)) => (),
+                other => panic!("expected {:?} to be rejected, found {:?}", remote_id, other),
+            }
+        }
+
+        let remote = DirectoryRemote { remote_id: "../offline".into(), ..remote };
+        assert!(remote.write_config(&directory).is_err());
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
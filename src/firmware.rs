@@ -0,0 +1,422 @@
+use crate::{
+    common::{self, HashWriter},
+    http::{
+        HttpRequest, HttpResponse, HttpTransport, STATUS_OK, STATUS_PARTIAL_CONTENT,
+        STATUS_RANGE_NOT_SATISFIABLE,
+    },
+    Device, Error, FlashEvent, Release, Remote, RemoteKind,
+};
+use crypto_hash::{Algorithm, Hasher};
+use progress_streams::ProgressWriter;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// Creates a GET request with the given user agent.
+pub(crate) fn get_request(user_agent: &str, uri: Url) -> HttpRequest {
+    HttpRequest::get(uri).header("User-Agent", user_agent)
+}
+
+/// Fetches firmware from a remote and caches it for later use.
+///
+/// Shared by the blocking and async clients, which are responsible for looking up the remote
+/// and user agent from the daemon beforehand.
+///
+/// Each location of the release is tried in order, until the firmware is downloaded from one of
+/// them with a valid checksum. Downloaded firmware is cached within `cache_dir`.
+pub(crate) fn fetch<C: FnMut(FlashEvent)>(
+    client: &dyn HttpTransport,
+    user_agent: &str,
+    cache_dir: &Path,
+    remote: &Remote,
+    device: &Device,
+    release: &Release,
+    mut callback: Option<C>,
+) -> Result<(PathBuf, Option<File>), Error> {
+    let locations = release.locations();
+    let primary = locations.first().cloned().unwrap_or_default();
+
+    // If remote is local, we already have the firmware.
+    {
+        let filename = match remote.kind {
+            RemoteKind::Local => Some(
+                Path::new(remote.filename_cache.as_ref())
+                    .parent()
+                    .expect("remote filename cache without parent")
+                    .join(Path::new(primary)),
+            ),
+            RemoteKind::Directory => Some(
+                Url::parse(primary)
+                    .ok()
+                    .and_then(|uri| uri.to_file_path().ok())
+                    .ok_or_else(|| Error::FirmwareLocation(primary.into()))?,
+            ),
+            _ => None,
+        };
+
+        if let Some(filename) = filename {
+            return Ok((filename, None));
+        }
+    }
+
+    // Create URIs, substituting if required. Locations which are not valid URIs are skipped.
+    let mut failures = Vec::new();
+    let mut mirrors: Vec<Url> = Vec::with_capacity(locations.len());
+    for &location in &locations {
+        match remote.firmware_uri(location) {
+            Ok(uri) => {
+                if !mirrors.contains(&uri) {
+                    mirrors.push(uri);
+                }
+            }
+            Err(why) => {
+                warn!("skipping firmware location {}: {}", location, why);
+                failures.push((Box::from(location), why));
+            }
+        }
+    }
+
+    if mirrors.is_empty() {
+        return Err(Error::FirmwareLocation(primary.into()));
+    }
+
+    // The cache path is derived from the first valid location, whichever mirror is downloaded
+    // from.
+    let file_path = common::cache_path_from_uri(cache_dir, &mirrors[0]);
+
+    let (checksum, algorithm) =
+        common::find_best_checksum(&release.checksums).ok_or(Error::ReleaseWithoutChecksums)?;
+
+    // If the firmware was already fetched, and the checksum is valid, the cached file is used.
+    if file_path.exists() {
+        info!("validating firmware for {} ({})", device.name, release.version);
+        let mut cache =
+            OpenOptions::new().read(true).open(&file_path).map_err(Error::FirmwareOpen)?;
+
+        if common::validate_checksum(&mut cache, checksum, algorithm).unwrap_or(false) {
+            cache.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
+            return Ok((file_path, Some(cache)));
+        }
+
+        let _ = fs::remove_file(&file_path);
+    }
+
+    let download = Download {
+        client,
+        user_agent,
+        remote,
+        checksum,
+        algorithm,
+        part_path: partial_path(&file_path),
+        size: release.size,
+    };
+
+    for uri in mirrors {
+        info!("downloading firmware for {} ({}) from {}", device.name, release.version, uri);
+        if let Some(ref mut cb) = callback {
+            cb(FlashEvent::DownloadMirror(uri.as_str().into()));
+        }
+
+        match download.from_mirror(uri.clone(), &mut callback) {
+            Ok(mut file) => {
+                fs::rename(&download.part_path, &file_path).map_err(Error::FirmwareRename)?;
+                file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
+                return Ok((file_path, Some(file)));
+            }
+            // Errors which are specific to the mirror fall back to the next mirror.
+            Err(why @ Error::FirmwareChecksumMismatch)
+            | Err(why @ Error::FirmwareCopy(_))
+            | Err(why @ Error::FirmwareGet(_)) => {
+                warn!("failed to download firmware from {}: {}", uri, why);
+                failures.push((Box::from(uri.as_str()), why));
+            }
+            Err(why) => return Err(why),
+        }
+    }
+
+    // A release with a single location fails with the error of that location.
+    if failures.len() == 1 {
+        return Err(failures.remove(0).1);
+    }
+
+    Err(Error::FirmwareMirrors(MirrorErrors(failures)))
+}
+
+/// The errors which occurred when downloading firmware from each mirror of a release.
+#[derive(Debug)]
+pub struct MirrorErrors(pub Vec<(Box<str>, Error)>);
+
+impl Display for MirrorErrors {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (id, (uri, why)) in self.0.iter().enumerate() {
+            if id != 0 {
+                f.write_str("; ")?;
+            }
+
+            write!(f, "{}: {}", uri, why)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MirrorErrors {}
+
+/// Downloads firmware to a partial file, which may be resumed from another mirror.
+struct Download<'a> {
+    client:     &'a dyn HttpTransport,
+    user_agent: &'a str,
+    remote:     &'a Remote,
+    checksum:   &'a str,
+    algorithm:  Algorithm,
+    part_path:  PathBuf,
+    size:       u64,
+}
+
+impl<'a> Download<'a> {
+    fn request(&self, uri: Url) -> HttpRequest {
+        let mut request = get_request(self.user_agent, uri);
+
+        // Set the username and password.
+        if let Some(ref username) = self.remote.username {
+            let password = self.remote.password.as_ref().map(AsRef::as_ref);
+            request = request.basic_auth(username, password);
+        }
+
+        request
+    }
+
+    /// Downloads the firmware from a mirror, and verifies its checksum.
+    fn from_mirror<C: FnMut(FlashEvent)>(
+        &self,
+        uri: Url,
+        callback: &mut Option<C>,
+    ) -> Result<File, Error> {
+        // Continues from the partial file left behind by an interrupted download, if there is one.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&self.part_path)
+            .map_err(Error::FirmwareCreate)?;
+
+        let mut offset = file.seek(SeekFrom::End(0)).map_err(Error::FirmwareSeek)?;
+
+        if let Some(ref mut cb) = callback {
+            cb(FlashEvent::DownloadInitiate(self.size));
+        }
+
+        let mut response = if offset == 0 {
+            self.client.get(&self.request(uri.clone()))
+        } else {
+            info!("resuming download of {} from byte {}", uri, offset);
+            let range = format!("bytes={}-", offset);
+            self.client.get(&self.request(uri.clone()).header("Range", &range))
+        }
+        .map_err(Error::FirmwareGet)?;
+
+        if offset != 0 && !resumes_from(&response, offset) {
+            // If the mirror failed, the partial file is kept for the next mirror to resume.
+            if response.status != STATUS_RANGE_NOT_SATISFIABLE {
+                response = response.error_for_status().map_err(Error::FirmwareGet)?;
+            }
+
+            info!("server did not resume the download of {}; restarting", uri);
+            file.set_len(0).map_err(Error::FirmwareCreate)?;
+            file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
+            offset = 0;
+
+            // A server which ignores the range has already sent the entire file.
+            if response.status != STATUS_OK {
+                response = self.client.get(&self.request(uri)).map_err(Error::FirmwareGet)?;
+            }
+        }
+
+        let mut response = response.error_for_status().map_err(Error::FirmwareGet)?;
+
+        // The bytes of a resumed download are hashed once, and the rest are hashed as they arrive.
+        let mut hasher = Hasher::new(self.algorithm);
+        if offset != 0 {
+            file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
+            io::copy(&mut (&mut file).take(offset), &mut hasher).map_err(Error::FirmwareRead)?;
+        }
+
+        let mut writer = HashWriter::with_hasher(&mut file, hasher);
+
+        let result = match callback {
+            Some(ref mut callback) => {
+                if offset != 0 {
+                    callback(FlashEvent::DownloadUpdate(offset as usize));
+                }
+
+                let mut progress = ProgressWriter::new(&mut writer, |progress| {
+                    callback(FlashEvent::DownloadUpdate(progress))
+                });
+
+                let result = io::copy(&mut response.body, &mut progress);
+                callback(FlashEvent::DownloadComplete);
+                result
+            }
+            None => io::copy(&mut response.body, &mut writer),
+        };
+
+        // The partial file is kept on failure, so that the next attempt may resume from it.
+        result.map_err(Error::FirmwareCopy)?;
+
+        if let Some(ref mut cb) = callback {
+            cb(FlashEvent::VerifyingChecksum);
+        }
+
+        if writer.finish() != self.checksum {
+            drop(file);
+            let _ = fs::remove_file(&self.part_path);
+            return Err(Error::FirmwareChecksumMismatch);
+        }
+
+        Ok(file)
+    }
+}
+
+/// The path that firmware is downloaded to, before its checksum has been verified.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut filename = path.file_name().expect("cache path without filename").to_owned();
+    filename.push(".part");
+    path.with_file_name(filename)
+}
+
+/// If the response continues the download from the given offset.
+fn resumes_from(response: &HttpResponse, offset: u64) -> bool {
+    response.status == STATUS_PARTIAL_CONTENT
+        && response.header("Content-Range").and_then(content_range_start) == Some(offset)
+}
+
+/// The first byte of a `Content-Range` header, such as `bytes 1024-2047/2048`.
+pub(crate) fn content_range_start(range: &str) -> Option<u64> {
+    let range = range.trim();
+    if !range.starts_with("bytes ") {
+        return None;
+    }
+
+    range[6..].split('-').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::temp_dir, http::StubTransport};
+
+    #[test]
+    fn fetch_resumes_from_mirror() {
+        let body: &'static [u8] = b"firmware payload which is downloaded in two parts";
+        let http = StubTransport { body, mirror: "mirror.fwupd-dbus.test" };
+
+        let remote = Remote { kind: RemoteKind::Download, ..Default::default() };
+        let release = Release {
+            checksums: vec![crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, body).into()]
+                .into_boxed_slice(),
+            locations: vec![
+                "https://primary.fwupd-dbus.test/stub.cab".into(),
+                "https://mirror.fwupd-dbus.test/stub.cab".into(),
+            ]
+            .into_boxed_slice(),
+            ..Default::default()
+        };
+
+        // The cache path is derived from the primary location, which the partial file shares.
+        let cache_dir = temp_dir("fetch-resumes-from-mirror");
+        let uri = remote.firmware_uri(&release.locations[0]).unwrap();
+        let cache = common::cache_path_from_uri(&cache_dir, &uri);
+        let part = cache.with_file_name("stub.cab.part");
+        std::fs::write(&part, &body[..16]).unwrap();
+
+        let mut mirrors = Vec::new();
+        let callback = |event: FlashEvent| {
+            if let FlashEvent::DownloadMirror(uri) = event {
+                mirrors.push(uri);
+            }
+        };
+
+        let device = Device::default();
+        let (path, _) =
+            fetch(&http, "fwupd/1.4.0", &cache_dir, &remote, &device, &release, Some(callback))
+                .unwrap();
+
+        assert_eq!(path, cache);
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert!(!part.exists());
+        assert_eq!(mirrors.len(), 2);
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn fetch_skips_invalid_locations() {
+        let body: &'static [u8] = b"firmware payload";
+        let http = StubTransport { body, mirror: "mirror.fwupd-dbus.test" };
+        let remote = Remote { kind: RemoteKind::Download, ..Default::default() };
+        let cache_dir = temp_dir("fetch-skips-invalid-locations");
+        let device = Device::default();
+
+        let fetch_from = |locations: &[&str]| {
+            let release = Release {
+                checksums: vec![crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, body)
+                    .into()]
+                .into_boxed_slice(),
+                locations: locations.iter().map(|&location| location.into()).collect(),
+                ..Default::default()
+            };
+
+            let callback = None::<fn(FlashEvent)>;
+            fetch(&http, "fwupd/1.4.0", &cache_dir, &remote, &device, &release, callback)
+        };
+
+        // A file name is relative to the URI of the remote, which this remote does not have.
+        let (path, _) =
+            fetch_from(&["stub.cab", "https://mirror.fwupd-dbus.test/stub.cab"]).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+
+        match fetch_from(&["stub.cab", "https://[::1/stub.cab"]) {
+            Err(Error::FirmwareLocation(location)) => assert_eq!(&*location, "stub.cab"),
+            other => panic!("expected every location to be invalid, found {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn fetch_from_directory() {
+        let http = StubTransport { body: b"", mirror: "mirror.fwupd-dbus.test" };
+        let remote = Remote { kind: RemoteKind::Directory, ..Default::default() };
+        let device = Device::default();
+        let fetch_location = |location: &str| {
+            let release = Release {
+                locations: vec![location.into()].into_boxed_slice(),
+                ..Default::default()
+            };
+
+            let callback = None::<fn(FlashEvent)>;
+            fetch(&http, "fwupd/1.4.0", Path::new(""), &remote, &device, &release, callback)
+        };
+
+        let (path, file) = fetch_location("file:///srv/firmware/mock%201.2.4.cab").unwrap();
+        assert_eq!(path, Path::new("/srv/firmware/mock 1.2.4.cab"));
+        assert!(file.is_none());
+
+        match fetch_location("mock.cab") {
+            Err(Error::FirmwareLocation(location)) => assert_eq!(&*location, "mock.cab"),
+            other => panic!("expected an invalid location, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn content_range_start() {
+        assert_eq!(content_range_start("bytes 1024-2047/2048"), Some(1024));
+        assert_eq!(content_range_start("bytes 0-99/*"), Some(0));
+        assert_eq!(content_range_start("bytes */2048"), None);
+        assert_eq!(content_range_start("items 0-1/2"), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::{self, Display, Formatter};
+
+const ERROR_PREFIX: &str = "org.freedesktop.fwupd.";
+
+/// Describes the kind of error that the daemon replied with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FwupdErrorKind {
+    /// Internal error.
+    Internal,
+    /// Installed version is newer than tried to install.
+    VersionNewer,
+    /// Installed version is identical to what was tried to install.
+    VersionSame,
+    /// Already set to be installed offline.
+    AlreadyPending,
+    /// Failed to get authentication.
+    AuthFailed,
+    /// Failed to read from the device.
+    Read,
+    /// Failed to write to the device.
+    Write,
+    /// Invalid file format.
+    InvalidFile,
+    /// No matching device exists.
+    NotFound,
+    /// Nothing to do.
+    NothingToDo,
+    /// Action was not possible.
+    NotSupported,
+    /// Signature was invalid.
+    SignatureInvalid,
+    /// AC power was required.
+    AcPowerRequired,
+    /// Permission was denied.
+    PermissionDenied,
+    /// User has configured their system in a broken way.
+    BrokenSystem,
+    /// The system battery level is too low.
+    BatteryLevelTooLow,
+    /// User needs to do an action to complete the update.
+    NeedsUserAction,
+}
+
+impl FwupdErrorKind {
+    /// Decodes the kind from a DBus error name, such as `org.freedesktop.fwupd.NothingToDo`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use self::FwupdErrorKind::*;
+        if !name.starts_with(ERROR_PREFIX) {
+            return None;
+        }
+
+        let kind = match &name[ERROR_PREFIX.len()..] {
+            "Internal" => Internal,
+            "VersionNewer" => VersionNewer,
+            "VersionSame" => VersionSame,
+            "AlreadyPending" => AlreadyPending,
+            "AuthFailed" => AuthFailed,
+            "Read" => Read,
+            "Write" => Write,
+            "InvalidFile" => InvalidFile,
+            "NotFound" => NotFound,
+            "NothingToDo" => NothingToDo,
+            "NotSupported" => NotSupported,
+            "SignatureInvalid" => SignatureInvalid,
+            "AcPowerRequired" => AcPowerRequired,
+            "PermissionDenied" => PermissionDenied,
+            "BrokenSystem" => BrokenSystem,
+            "BatteryLevelTooLow" => BatteryLevelTooLow,
+            "NeedsUserAction" => NeedsUserAction,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    /// The name of the error, without the `org.freedesktop.fwupd.` prefix.
+    pub fn name(self) -> &'static str {
+        use self::FwupdErrorKind::*;
+        match self {
+            Internal => "Internal",
+            VersionNewer => "VersionNewer",
+            VersionSame => "VersionSame",
+            AlreadyPending => "AlreadyPending",
+            AuthFailed => "AuthFailed",
+            Read => "Read",
+            Write => "Write",
+            InvalidFile => "InvalidFile",
+            NotFound => "NotFound",
+            NothingToDo => "NothingToDo",
+            NotSupported => "NotSupported",
+            SignatureInvalid => "SignatureInvalid",
+            AcPowerRequired => "AcPowerRequired",
+            PermissionDenied => "PermissionDenied",
+            BrokenSystem => "BrokenSystem",
+            BatteryLevelTooLow => "BatteryLevelTooLow",
+            NeedsUserAction => "NeedsUserAction",
+        }
+    }
+}
+
+impl Display for FwupdErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result { f.write_str(self.name()) }
+}
+
+/// An error which the daemon replied with, and the message that it was given.
+#[derive(Clone, Debug, Error)]
+#[error(display = "fwupd replied with {}: {}", kind, message)]
+pub struct FwupdError {
+    pub kind:    FwupdErrorKind,
+    pub message: Box<str>,
+}
+
+impl FwupdError {
+    /// Decodes the error if it was replied by the daemon.
+    pub(crate) fn from_dbus(why: &dbus::Error) -> Option<Self> {
+        let kind = FwupdErrorKind::from_name(why.name()?)?;
+        Some(FwupdError { kind, message: why.message().unwrap_or("").into() })
+    }
+}
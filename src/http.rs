@@ -0,0 +1,140 @@
+use std::{error::Error as StdError, io::Read};
+use url::Url;
+
+/// The status of a response which contains the entire body.
+pub(crate) const STATUS_OK: u16 = 200;
+/// The status of a response which resumes a download from the requested range.
+pub(crate) const STATUS_PARTIAL_CONTENT: u16 = 206;
+/// The status of a response to a range which begins after the end of the body.
+pub(crate) const STATUS_RANGE_NOT_SATISFIABLE: u16 = 416;
+
+/// An error that may occur when sending a request with an `HttpTransport`.
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error(display = "failed to send request to {}: {}", _0, _1)]
+    Request(Url, Box<dyn StdError + Send + Sync>),
+    #[error(display = "{} responded with status {}", _0, _1)]
+    Status(Url, u16),
+}
+
+/// A GET request, which is sent by an `HttpTransport`.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub uri:        Url,
+    pub headers:    Vec<(Box<str>, Box<str>)>,
+    /// The username and password to authenticate with, if the remote requires it.
+    pub basic_auth: Option<(Box<str>, Option<Box<str>>)>,
+}
+
+impl HttpRequest {
+    pub fn get(uri: Url) -> Self { Self { uri, headers: Vec::new(), basic_auth: None } }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn basic_auth(mut self, username: &str, password: Option<&str>) -> Self {
+        self.basic_auth = Some((username.into(), password.map(Box::from)));
+        self
+    }
+}
+
+/// The response to an `HttpRequest`, whose body is streamed as it is read.
+pub struct HttpResponse {
+    pub uri:     Url,
+    pub status:  u16,
+    pub headers: Vec<(Box<str>, Box<str>)>,
+    pub body:    Box<dyn Read + Send>,
+}
+
+impl HttpResponse {
+    /// The value of the first header with the given name, which is compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_ref())
+    }
+
+    /// Fails if the server responded with a client or server error.
+    pub fn error_for_status(self) -> Result<Self, HttpError> {
+        if self.status >= 400 {
+            Err(HttpError::Status(self.uri, self.status))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+/// Sends the HTTP requests which fetch firmware and metadata from remotes.
+///
+/// With the default `reqwest` feature, this is implemented for `reqwest::blocking::Client`.
+/// Applications may implement it to use their own client, such as one which is proxy-aware.
+pub trait HttpTransport {
+    fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError>;
+}
+
+#[cfg(feature = "reqwest")]
+impl HttpTransport for reqwest::blocking::Client {
+    fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut builder = reqwest::blocking::Client::get(self, request.uri.clone());
+
+        for (name, value) in &request.headers {
+            builder = builder.header(&**name, &**value);
+        }
+
+        if let Some((ref username, ref password)) = request.basic_auth {
+            builder = builder.basic_auth(username, password.as_ref());
+        }
+
+        let response =
+            builder.send().map_err(|why| HttpError::Request(request.uri.clone(), Box::new(why)))?;
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().into(), value.to_str().ok()?.into())))
+            .collect();
+
+        Ok(HttpResponse {
+            uri: request.uri.clone(),
+            status: response.status().as_u16(),
+            headers,
+            body: Box::new(response),
+        })
+    }
+}
+
+/// Serves a body from one mirror, and fails on every other mirror.
+#[cfg(test)]
+pub(crate) struct StubTransport {
+    pub body:   &'static [u8],
+    pub mirror: &'static str,
+}
+
+#[cfg(test)]
+impl HttpTransport for StubTransport {
+    fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let range = request.headers.iter().find(|(name, _)| &**name == "Range");
+        let offset = range.map_or(0, |(_, value)| {
+            value.trim_start_matches("bytes=").trim_end_matches('-').parse().unwrap()
+        });
+
+        let (status, headers) = if request.uri.host_str() != Some(self.mirror) {
+            (404, Vec::new())
+        } else if offset == 0 {
+            (STATUS_OK, Vec::new())
+        } else {
+            let range = format!("bytes {}-{}/{}", offset, self.body.len() - 1, self.body.len());
+            (STATUS_PARTIAL_CONTENT, vec![("Content-Range".into(), range.into())])
+        };
+
+        Ok(HttpResponse {
+            uri: request.uri.clone(),
+            status,
+            headers,
+            body: Box::new(std::io::Cursor::new(&self.body[offset..])),
+        })
+    }
+}
@@ -0,0 +1,395 @@
+//! Reads and writes Jcat files, which hold the checksums and signatures of remote metadata.
+//!
+//! A Jcat file is gzipped JSON, containing an item for each file that it describes, and a blob
+//! for each checksum or signature of that file.
+
+use crate::{common::GZIP_MAGIC, KeyringKind};
+use crypto_hash::{hex_digest, Algorithm};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// An error that may occur when reading or validating a Jcat file.
+#[derive(Debug, Error)]
+pub enum JcatError {
+    #[error(display = "the {:?} checksum of {} does not match", _1, _0)]
+    ChecksumMismatch(Box<str>, JcatBlobKind),
+    #[error(display = "invalid base64 data in a blob of {}", _0)]
+    Decode(Box<str>, #[error(cause, no_from)] base64::DecodeError),
+    #[error(display = "failed to parse the JSON of the Jcat file")]
+    Json(#[error(cause, no_from)] serde_json::Error),
+    #[error(display = "{} does not have any checksums", _0)]
+    NoChecksums(Box<str>),
+    #[error(display = "the Jcat file does not describe {}", _0)]
+    NoItem(Box<str>),
+    #[error(display = "failed to read the Jcat file")]
+    Read(#[error(cause, no_from)] io::Error),
+    #[error(display = "failed to write the Jcat file")]
+    Write(#[error(cause, no_from)] io::Error),
+}
+
+/// The kind of data that a blob holds.
+///
+/// Kinds which are not known to this crate keep their value, so that they are written back as-is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JcatBlobKind {
+    Unknown(u32),
+    Sha256,
+    Gpg,
+    Pkcs7,
+    Sha1,
+}
+
+impl Default for JcatBlobKind {
+    fn default() -> Self { JcatBlobKind::Unknown(0) }
+}
+
+impl From<u32> for JcatBlobKind {
+    fn from(value: u32) -> Self {
+        use self::JcatBlobKind::*;
+        match value {
+            1 => Sha256,
+            2 => Gpg,
+            3 => Pkcs7,
+            4 => Sha1,
+            value => Unknown(value),
+        }
+    }
+}
+
+impl From<JcatBlobKind> for u32 {
+    fn from(kind: JcatBlobKind) -> Self {
+        use self::JcatBlobKind::*;
+        match kind {
+            Unknown(value) => value,
+            Sha256 => 1,
+            Gpg => 2,
+            Pkcs7 => 3,
+            Sha1 => 4,
+        }
+    }
+}
+
+impl JcatBlobKind {
+    /// The keyring that verifies the blob, if it is a signature.
+    pub fn keyring(self) -> Option<KeyringKind> {
+        match self {
+            JcatBlobKind::Gpg => Some(KeyringKind::GPG),
+            JcatBlobKind::Pkcs7 => Some(KeyringKind::PKCS7),
+            _ => None,
+        }
+    }
+
+    fn algorithm(self) -> Option<Algorithm> {
+        match self {
+            JcatBlobKind::Sha256 => Some(Algorithm::SHA256),
+            JcatBlobKind::Sha1 => Some(Algorithm::SHA1),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// Describes how the data of a blob is stored.
+    pub struct JcatBlobFlags: u32 {
+        /// The data is stored as text, rather than as base64.
+        const IS_UTF8 = 1;
+    }
+}
+
+impl Default for JcatBlobFlags {
+    fn default() -> Self { JcatBlobFlags::empty() }
+}
+
+/// A checksum or signature of an item.
+#[derive(Clone, Debug, Default)]
+pub struct JcatBlob {
+    pub appstream_id: Option<Box<str>>,
+    pub data:         Vec<u8>,
+    pub flags:        JcatBlobFlags,
+    pub kind:         JcatBlobKind,
+    pub timestamp:    u64,
+}
+
+impl JcatBlob {
+    /// Creates a blob with the hex checksum of the data, unless the kind is not a checksum.
+    pub fn checksum(kind: JcatBlobKind, data: &[u8]) -> Option<Self> {
+        kind.algorithm().map(|algorithm| Self::text(kind, &hex_digest(algorithm, data)))
+    }
+
+    /// Creates a blob which stores the data as text, such as an armored GPG signature.
+    pub fn text(kind: JcatBlobKind, text: &str) -> Self {
+        JcatBlob {
+            data: text.as_bytes().to_vec(),
+            flags: JcatBlobFlags::IS_UTF8,
+            kind,
+            timestamp: now(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The checksums and signatures of a file, such as `firmware.xml.gz`.
+#[derive(Clone, Debug, Default)]
+pub struct JcatItem {
+    /// Other names of the file, which the item may also be found by.
+    pub alias_ids: Vec<Box<str>>,
+    pub blobs:     Vec<JcatBlob>,
+    pub id:        Box<str>,
+}
+
+impl JcatItem {
+    pub fn new(id: &str) -> Self { JcatItem { id: id.into(), ..Default::default() } }
+
+    /// Validates every checksum of the item against the data.
+    ///
+    /// Fails if the item does not have any checksums.
+    pub fn validate_checksums(&self, data: &[u8]) -> Result<(), JcatError> {
+        let mut validated = false;
+
+        for blob in &self.blobs {
+            if let Some(algorithm) = blob.kind.algorithm() {
+                let expected = String::from_utf8_lossy(&blob.data);
+                if !expected.trim().eq_ignore_ascii_case(&hex_digest(algorithm, data)) {
+                    return Err(JcatError::ChecksumMismatch(self.id.clone(), blob.kind));
+                }
+
+                validated = true;
+            }
+        }
+
+        if validated {
+            Ok(())
+        } else {
+            Err(JcatError::NoChecksums(self.id.clone()))
+        }
+    }
+
+    /// The signatures of the item, with the keyring which verifies each of them.
+    pub fn signatures(&self) -> impl Iterator<Item = (KeyringKind, &[u8])> {
+        self.blobs
+            .iter()
+            .filter_map(|blob| blob.kind.keyring().map(|keyring| (keyring, blob.data.as_slice())))
+    }
+}
+
+/// The contents of a Jcat file.
+#[derive(Clone, Debug)]
+pub struct JcatFile {
+    pub items:         Vec<JcatItem>,
+    pub version_major: u32,
+    pub version_minor: u32,
+}
+
+impl Default for JcatFile {
+    fn default() -> Self { JcatFile { items: Vec::new(), version_major: 0, version_minor: 1 } }
+}
+
+impl JcatFile {
+    /// Parses a Jcat file, which may be compressed with gzip.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, JcatError> {
+        let mut reader = BufReader::new(reader);
+        let compressed = reader.fill_buf().map_err(JcatError::Read)?.starts_with(GZIP_MAGIC);
+
+        let raw: raw::File = if compressed {
+            serde_json::from_reader(GzDecoder::new(reader))
+        } else {
+            serde_json::from_reader(reader)
+        }
+        .map_err(JcatError::Json)?;
+
+        raw.decode()
+    }
+
+    /// Writes the Jcat file as gzipped JSON.
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<(), JcatError> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        serde_json::to_writer_pretty(&mut encoder, &raw::File::encode(self))
+            .map_err(JcatError::Json)?;
+        encoder.finish().map_err(JcatError::Write)?;
+        Ok(())
+    }
+
+    /// The item with the given ID or alias.
+    pub fn item(&self, id: &str) -> Option<&JcatItem> {
+        self.items
+            .iter()
+            .find(|item| &*item.id == id || item.alias_ids.iter().any(|alias| &**alias == id))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+/// The JSON representation of a Jcat file.
+mod raw {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize)]
+    pub struct File {
+        #[serde(rename = "JcatVersionMajor", default)]
+        version_major: u32,
+        #[serde(rename = "JcatVersionMinor", default)]
+        version_minor: u32,
+        #[serde(rename = "Items", default)]
+        items:         Vec<Item>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Item {
+        #[serde(rename = "Id")]
+        id:        String,
+        #[serde(rename = "AliasIds", default, skip_serializing_if = "Vec::is_empty")]
+        alias_ids: Vec<String>,
+        #[serde(rename = "Blobs", default)]
+        blobs:     Vec<Blob>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Blob {
+        #[serde(rename = "Kind")]
+        kind:         u32,
+        #[serde(rename = "Flags", default)]
+        flags:        u32,
+        #[serde(rename = "AppstreamId", default, skip_serializing_if = "Option::is_none")]
+        appstream_id: Option<String>,
+        #[serde(rename = "Timestamp", default)]
+        timestamp:    u64,
+        #[serde(rename = "Data", default)]
+        data:         String,
+    }
+
+    impl File {
+        pub fn decode(self) -> Result<JcatFile, JcatError> {
+            let mut items = Vec::with_capacity(self.items.len());
+
+            for item in self.items {
+                let mut blobs = Vec::with_capacity(item.blobs.len());
+                for blob in item.blobs {
+                    let flags = JcatBlobFlags::from_bits_truncate(blob.flags);
+                    let data = if flags.contains(JcatBlobFlags::IS_UTF8) {
+                        blob.data.into_bytes()
+                    } else {
+                        base64::decode(&blob.data)
+                            .map_err(|why| JcatError::Decode(item.id.as_str().into(), why))?
+                    };
+
+                    blobs.push(JcatBlob {
+                        appstream_id: blob.appstream_id.map(Box::from),
+                        data,
+                        flags,
+                        kind: JcatBlobKind::from(blob.kind),
+                        timestamp: blob.timestamp,
+                    });
+                }
+
+                items.push(JcatItem {
+                    alias_ids: item.alias_ids.into_iter().map(Box::from).collect(),
+                    blobs,
+                    id: item.id.into(),
+                });
+            }
+
+            Ok(JcatFile {
+                items,
+                version_major: self.version_major,
+                version_minor: self.version_minor,
+            })
+        }
+
+        pub fn encode(file: &JcatFile) -> Self {
+            let blob = |blob: &JcatBlob| Blob {
+                kind:         blob.kind.into(),
+                flags:        blob.flags.bits(),
+                appstream_id: blob.appstream_id.as_ref().map(|id| id.to_string()),
+                timestamp:    blob.timestamp,
+                data:         if blob.flags.contains(JcatBlobFlags::IS_UTF8) {
+                    String::from_utf8_lossy(&blob.data).into_owned()
+                } else {
+                    base64::encode(&blob.data)
+                },
+            };
+
+            let item = |item: &JcatItem| Item {
+                id:        item.id.to_string(),
+                alias_ids: item.alias_ids.iter().map(|id| id.to_string()).collect(),
+                blobs:     item.blobs.iter().map(blob).collect(),
+            };
+
+            File {
+                version_major: file.version_major,
+                version_minor: file.version_minor,
+                items:         file.items.iter().map(item).collect(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UpdateError;
+
+    #[test]
+    fn jcat_checksums() {
+        let metadata = b"<components/>";
+
+        let mut item = JcatItem::new("firmware.xml.gz");
+        item.blobs.push(JcatBlob::checksum(JcatBlobKind::Sha256, metadata).unwrap());
+        assert!(JcatBlob::checksum(JcatBlobKind::Gpg, metadata).is_none());
+        item.blobs.push(JcatBlob::text(JcatBlobKind::Gpg, "-----BEGIN PGP SIGNATURE-----"));
+
+        let mut buffer = Vec::new();
+        JcatFile { items: vec![item], ..Default::default() }
+            .to_writer(&mut buffer)
+            .expect("failed to write jcat file");
+
+        let file = JcatFile::from_reader(buffer.as_slice()).expect("failed to read jcat file");
+        let item = file.item("firmware.xml.gz").expect("item is missing");
+
+        assert!(item.validate_checksums(metadata).is_ok());
+        assert!(item.validate_checksums(b"<components></components>").is_err());
+        assert_eq!(item.signatures().map(|(keyring, _)| keyring).collect::<Vec<_>>(), vec![
+            KeyringKind::GPG
+        ]);
+
+        // The item is found by the last segment of the path, ignoring the query of the URI.
+        let validate = |uri: &str| crate::remote::validate_jcat(uri, metadata, &buffer);
+        assert!(validate("https://cdn.fwupd.org/downloads/firmware.xml.gz?v=2").is_ok());
+        match validate("https://cdn.fwupd.org/downloads/firmware.xml.gz/") {
+            Err(UpdateError::Jcat(JcatError::NoItem(id))) => assert_eq!(&*id, ""),
+            other => panic!("expected the item to be missing, found {:?}", other),
+        }
+
+        assert_eq!(KeyringKind::Jcat.signature_suffix(), ".jcat");
+        assert_eq!(KeyringKind::GPG.signature_suffix(), ".asc");
+    }
+
+    #[test]
+    fn jcat_unknown_kind() {
+        let json = br#"{
+            "JcatVersionMajor": 0,
+            "JcatVersionMinor": 1,
+            "Items": [{
+                "Id": "firmware.xml.gz",
+                "Blobs": [{ "Kind": 7, "Flags": 1, "Timestamp": 0, "Data": "ed25519" }]
+            }]
+        }"#;
+
+        let file = JcatFile::from_reader(&json[..]).expect("failed to read jcat file");
+        let kind = file.items[0].blobs[0].kind;
+        assert_eq!(kind, JcatBlobKind::Unknown(7));
+        assert_eq!(kind.keyring(), None);
+
+        let mut buffer = Vec::new();
+        file.to_writer(&mut buffer).expect("failed to write jcat file");
+        let file = JcatFile::from_reader(buffer.as_slice()).expect("failed to read jcat file");
+        assert_eq!(file.items[0].blobs[0].kind, JcatBlobKind::Unknown(7));
+        assert_eq!(u32::from(JcatBlobKind::Pkcs7), 3);
+        assert_eq!(JcatBlobKind::default(), JcatBlobKind::Unknown(0));
+    }
+}
@@ -9,17 +9,60 @@ extern crate log;
 #[macro_use]
 extern crate shrinkwraprs;
 
+mod appstream;
+mod builder;
+mod cab;
 mod common;
 mod dbus_helpers;
 mod device;
+mod directory;
+mod firmware;
+mod fwupd_error;
+mod http;
+mod jcat;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "async")]
+mod nonblock;
+mod policy;
+mod properties;
 mod release;
 mod remote;
-
-pub use self::{device::*, release::*, remote::*};
+mod request;
+mod security;
+mod signature;
+mod signal;
+mod value;
+mod version;
+
+#[cfg(feature = "async")]
+pub use self::nonblock::{AsyncClient, SignalStream};
+pub use self::{
+    appstream::*,
+    builder::{Bus, ClientBuilder},
+    cab::*,
+    dbus_helpers::DecodeError,
+    device::*,
+    directory::*,
+    firmware::MirrorErrors,
+    fwupd_error::{FwupdError, FwupdErrorKind},
+    http::{HttpError, HttpRequest, HttpResponse, HttpTransport},
+    jcat::*,
+    policy::FirmwarePolicy,
+    properties::DaemonProperties,
+    release::*,
+    remote::*,
+    request::*,
+    security::*,
+    signature::{SignatureError, TrustRoots, TRUST_ROOTS_DIR},
+    signal::*,
+    value::Value,
+    version::Version,
+};
 
 use dbus::{
     self,
-    arg::{Arg, Array, Dict, Get, OwnedFd, RefArg, Variant},
+    arg::{Arg, Get, OwnedFd, RefArg, Variant},
     ffidisp::{
         Connection, ConnectionItem, ConnPath,
         stdintf::org_freedesktop_dbus::{Peer, Properties},
@@ -27,42 +70,86 @@ use dbus::{
     Message,
 };
 
-use progress_streams::ProgressWriter;
-use reqwest::{
-    header::{HeaderValue, USER_AGENT},
-    blocking::Client as HttpClient, IntoUrl,
-};
 use std::{
     borrow::Cow,
+    cell::RefCell,
     collections::HashMap,
-    fs::{self, File, OpenOptions},
-    io::{self, Seek, SeekFrom},
-    iter::FromIterator,
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io,
+    ops::Deref,
     os::unix::io::IntoRawFd,
     path::{Path, PathBuf},
+    rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc,
     },
+    time::{Duration, Instant},
 };
+use url::Url;
 
 pub const DBUS_NAME: &str = "org.freedesktop.fwupd";
 pub const DBUS_IFACE: &str = "org.freedesktop.fwupd";
 pub const DBUS_PATH: &str = "/";
 
+/// Waits forever for a reply, unless a timeout has been set on the client.
 const TIMEOUT: i32 = -1;
 
+/// How long to wait for a reply before checking if a cancellable call was cancelled, in
+/// milliseconds.
+const CANCEL_POLL_TIMEOUT: u32 = 100;
+
+/// How long to wait for a signal before checking if listening was cancelled, in milliseconds.
+const SIGNAL_POLL_TIMEOUT: i32 = 500;
+
+pub(crate) const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+
 pub type DynVariant = Variant<Box<dyn RefArg + 'static>>;
 pub type DBusEntry = (String, DynVariant);
+pub type DBusEntries = HashMap<String, DynVariant>;
+
+bitflags! {
+    /// Describes the features which a client supports, and which the daemon may rely upon.
+    pub struct FeatureFlags: u64 {
+        /// Can upload a report of the update to a remote server
+        const CAN_REPORT           = 1;
+        /// Can show the instructions to detach a device before an update
+        const DETACH_ACTION        = 1 << 1;
+        /// Can show the instructions to complete an update
+        const UPDATE_ACTION        = 1 << 2;
+        /// Can switch the firmware branch of a device
+        const SWITCH_BRANCH        = 1 << 3;
+        /// Can show requests from the daemon, such as to replug a device
+        const REQUESTS             = 1 << 4;
+        /// Can warn about full disk encryption
+        const FDE_WARNING          = 1 << 5;
+        /// Can show the warning for community supported firmware
+        const COMMUNITY_TEXT       = 1 << 6;
+        /// Can show the problems which prevent an update
+        const SHOW_PROBLEMS        = 1 << 7;
+        /// Can authenticate the user with polkit
+        const ALLOW_AUTHENTICATION = 1 << 8;
+    }
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self { FeatureFlags::empty() }
+}
 
 bitflags! {
     /// Controls the behavior of the install method.
-    pub struct InstallFlags: u8 {
-        const OFFLINE         = 1;
-        const ALLOW_REINSTALL = 1 << 1;
-        const ALLOW_OLDER     = 1 << 2;
-        const FORCE           = 1 << 3;
-        const NO_HISTORY      = 1 << 4;
+    pub struct InstallFlags: u32 {
+        const OFFLINE             = 1;
+        const ALLOW_REINSTALL     = 1 << 1;
+        const ALLOW_OLDER         = 1 << 2;
+        const FORCE               = 1 << 3;
+        const NO_HISTORY          = 1 << 4;
+        const ALLOW_BRANCH_SWITCH = 1 << 5;
+        const IGNORE_CHECKSUM     = 1 << 6;
+        const IGNORE_VID_PID      = 1 << 7;
+        const IGNORE_POWER        = 1 << 8;
+        const NO_SEARCH           = 1 << 9;
     }
 }
 
@@ -85,6 +172,10 @@ pub enum Status {
     Shutdown,
 }
 
+impl Default for Status {
+    fn default() -> Self { Status::Unknown }
+}
+
 impl From<u8> for Status {
     fn from(value: u8) -> Self {
         use self::Status::*;
@@ -103,7 +194,7 @@ impl From<u8> for Status {
             11 => DeviceBusy,
             12 => Shutdown,
             _ => {
-                eprintln!("status value {} is out of range", value);
+                warn!("status value {} is out of range", value);
                 Idle
             }
         }
@@ -113,6 +204,8 @@ impl From<u8> for Status {
 #[derive(Debug)]
 pub enum FlashEvent {
     DownloadInitiate(u64),
+    /// The URI of the mirror which the firmware is being downloaded from.
+    DownloadMirror(Box<str>),
     DownloadUpdate(usize),
     DownloadComplete,
     VerifyingChecksum,
@@ -126,26 +219,44 @@ pub enum Error {
     AddMatch(#[error(cause, no_from)] dbus::Error),
     #[error(display = "argument mismatch in {} method", _0)]
     ArgumentMismatch(&'static str, #[error(cause, no_from)] dbus::arg::TypeMismatchError),
+    #[error(display = "the blocking task of {} did not complete: {}", _0, _1)]
+    BlockingTask(&'static str, Box<dyn std::error::Error + Send + Sync>),
     #[error(display = "calling {} method failed", _0)]
     Call(&'static str, #[error(cause, no_from)] dbus::Error),
+    #[error(display = "{} method was cancelled", _0)]
+    Cancelled(&'static str),
     #[error(display = "unable to establish dbus connection")]
     Connection(#[error(cause, no_from)] dbus::Error),
+    #[error(display = "failed to decode the reply of the {} method", _0)]
+    Decode(&'static str, #[error(cause, no_from)] DecodeError),
     #[error(display = "the remote firmware which was downloaded has an invalid checksum")]
     FirmwareChecksumMismatch,
     #[error(display = "failed to copy firmware file from remote")]
     FirmwareCopy(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to create firmware file in user cache")]
     FirmwareCreate(#[error(cause, no_from)] io::Error),
+    #[error(display = "calling {} method failed", _0)]
+    Fwupd(&'static str, #[error(cause, no_from)] FwupdError),
     #[error(display = "failed to GET firmware file from remote")]
-    FirmwareGet(#[error(cause, no_from)] reqwest::Error),
+    FirmwareGet(#[error(cause, no_from)] HttpError),
+    #[error(display = "the firmware location is not usable: {}", _0)]
+    FirmwareLocation(Box<str>),
     #[error(display = "failed to open firmware file")]
     FirmwareOpen(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to read firmware file")]
     FirmwareRead(#[error(cause, no_from)] io::Error),
+    #[error(display = "failed to download firmware from every mirror")]
+    FirmwareMirrors(#[error(cause, no_from)] MirrorErrors),
+    #[error(display = "failed to move downloaded firmware file into the user cache")]
+    FirmwareRename(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to seek to beginning of firmware file")]
     FirmwareSeek(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to get property for {}", _0)]
     GetProperty(&'static str, #[error(cause, no_from)] dbus::Error),
+    #[error(display = "invalid bus name: {}", _0)]
+    InvalidBusName(String),
+    #[error(display = "invalid object path: {}", _0)]
+    InvalidPath(String),
     #[error(display = "unable to ping the dbus daemon")]
     Ping(#[error(cause, no_from)] dbus::Error),
     #[error(display = "failed to create {} method call", _0)]
@@ -154,21 +265,92 @@ pub enum Error {
     ReleaseWithoutChecksums,
     #[error(display = "remote not found")]
     RemoteNotFound,
+    #[error(display = "failed to send {} method call", _0)]
+    Send(&'static str),
+    #[error(display = "timed out waiting for a reply from {}", _0)]
+    Timeout(&'static str),
+}
+
+impl Error {
+    /// The error that the daemon replied with, if the daemon rejected the call.
+    pub fn fwupd_error(&self) -> Option<&FwupdError> {
+        match self {
+            Error::Fwupd(_, why) => Some(why),
+            _ => None,
+        }
+    }
 }
 
 /// A DBus client for interacting with the fwupd daemon.
-#[derive(Shrinkwrap)]
+///
+/// Clones of the client share its connection.
+#[derive(Clone)]
 pub struct Client {
-    #[shrinkwrap(main_field)]
-    connection: Connection,
-    user_agent: RwLock<Option<Box<str>>>,
+    connection:  Rc<Connection>,
+    bus_name:    Box<str>,
+    path:        Box<str>,
+    cancellable: Option<Arc<AtomicBool>>,
+    timeout:     Option<Duration>,
+    user_agent:  Rc<RefCell<Option<Box<str>>>>,
+}
+
+impl Deref for Client {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection { &self.connection }
+}
+
+impl AsRef<Connection> for Client {
+    fn as_ref(&self) -> &Connection { &self.connection }
 }
 
 impl Client {
-    pub fn new() -> Result<Self, Error> {
-        Connection::new_system()
-            .map_err(Error::Connection)
-            .map(|connection| Self { connection, user_agent: RwLock::new(None) })
+    /// Connects to the daemon on the system bus.
+    pub fn new() -> Result<Self, Error> { ClientBuilder::new().build() }
+
+    /// Connects to a daemon on the session bus.
+    pub fn session() -> Result<Self, Error> { ClientBuilder::new().bus(Bus::Session).build() }
+
+    /// Connects to a daemon on the bus at the given address.
+    pub fn with_address(address: &str) -> Result<Self, Error> {
+        ClientBuilder::new().bus(Bus::Address(address.into())).build()
+    }
+
+    /// Uses a connection which has already been opened.
+    pub fn with_connection(connection: Connection) -> Self {
+        ClientBuilder::new().into_client(connection)
+    }
+
+    /// Configures the bus, bus name, and object path to connect to.
+    pub fn builder() -> ClientBuilder { ClientBuilder::new() }
+
+    /// How long method calls wait for a reply before failing with `Error::Timeout`.
+    ///
+    /// `None` waits forever, which is the default.
+    pub fn timeout(&self) -> Option<Duration> { self.timeout }
+
+    /// Sets how long method calls wait for a reply before failing with `Error::Timeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) { self.timeout = timeout; }
+
+    /// Creates a client sharing this connection, whose method calls use a different timeout.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// let client = fwupd_dbus::Client::new().unwrap();
+    /// let devices = client.with_timeout(Some(Duration::from_secs(5))).devices();
+    /// ```
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self.clone() }
+    }
+
+    /// Creates a client sharing this connection, whose method calls are cancellable.
+    ///
+    /// Once `cancellable` has been set to `true`, the pending call stops waiting for its reply
+    /// and fails with `Error::Cancelled`. The daemon is not told to stop what it was doing.
+    /// Messages other than the reply that arrive while waiting are discarded, so signals should
+    /// be listened to from a client with its own connection.
+    pub fn with_cancellable(&self, cancellable: Arc<AtomicBool>) -> Self {
+        Self { cancellable: Some(cancellable), ..self.clone() }
     }
 
     /// Activate a firmware update on the device.
@@ -176,11 +358,60 @@ impl Client {
         self.action_method("Activate", id.as_ref().as_ref())
     }
 
+    /// The checksums of firmware which have been approved for installation.
+    pub fn approved_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.get_checksums("GetApprovedFirmware")
+    }
+
+    /// The system battery level, as a percentage, if it is known.
+    pub fn battery_level(&self) -> Result<Option<u8>, Error> {
+        self.get_property::<u32>("BatteryLevel").map(properties::battery_level)
+    }
+
+    /// The battery level required for updates, as a percentage, if it is known.
+    pub fn battery_threshold(&self) -> Result<Option<u8>, Error> {
+        self.get_property::<u32>("BatteryThreshold").map(properties::battery_level)
+    }
+
+    /// The checksums of firmware which may never be installed.
+    pub fn blocked_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.get_checksums("GetBlockedFirmware")
+    }
+
+    /// The releases of a device which the approved and blocked firmware lists would block, and
+    /// the flags that explain why.
+    pub fn blocked_releases<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+    ) -> Result<Vec<(Release, ReleaseFlags)>, Error> {
+        let policy = self.firmware_policy()?;
+        let releases = self.releases(device_id)?;
+        Ok(policy.blocked(&releases).into_iter().map(|(r, flags)| (r.clone(), flags)).collect())
+    }
+
+    /// The releases of a device which belong to a different branch than its installed firmware.
+    ///
+    /// Installing one of these releases with `switch_branch()` moves the device to its branch.
+    pub fn branch_releases(&self, device: &Device) -> Result<Vec<Release>, Error> {
+        let releases = self.releases(device)?;
+        Ok(releases.into_iter().filter(|release| release.branch != device.branch).collect())
+    }
+
     /// Clears the results of an offline update.
     pub fn clear_results<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
         self.action_method("ClearResults", id.as_ref().as_ref())
     }
 
+    /// Gets every property of the daemon in a single call.
+    pub fn daemon_properties(&self) -> Result<DaemonProperties, Error> {
+        const METHOD: &str = "GetAll";
+
+        let properties =
+            self.timed(METHOD, call_error, || self.connection_path().get_all(DBUS_IFACE))?;
+
+        DaemonProperties::try_from(properties).map_err(|why| Error::Decode(METHOD, why))
+    }
+
     /// The version of this daemon.
     pub fn daemon_version(&self) -> Result<Box<str>, Error> {
         self.get_property::<String>("DaemonVersion").map(Box::from)
@@ -205,138 +436,26 @@ impl Client {
     /// Fetches firmware from a remote and caches it for later use.
     ///
     /// Firmware will only be fetched if it has not already been cached, or the cached firmware has
-    /// an invalid checksum.
+    /// an invalid checksum. An interrupted download is resumed by the next fetch, if the server
+    /// supports range requests.
     pub fn fetch_firmware_from_release<C: FnMut(FlashEvent)>(
         &self,
-        client: &HttpClient,
+        client: &dyn HttpTransport,
         device: &Device,
         release: &Release,
-        mut callback: Option<C>,
+        callback: Option<C>,
     ) -> Result<(PathBuf, Option<File>), Error> {
         let remote = self.remote(release)?;
-
-        // If remote is local, we already have the firmware.
-        {
-            let filename: Option<Cow<'_, Path>> = match remote.kind {
-                RemoteKind::Local => Some(Cow::Owned(
-                    Path::new(remote.filename_cache.as_ref())
-                        .parent()
-                        .expect("remote filename cache without parent")
-                        .join(Path::new(release.uri.as_ref())),
-                )),
-                RemoteKind::Directory => Some(Cow::Borrowed(Path::new(&release.uri[7..]))),
-                _ => None,
-            };
-
-            if let Some(filename) = filename {
-                return Ok((filename.to_path_buf(), None));
-            }
-        }
-
-        // Create URI, substituting if required.
-        let uri = remote.firmware_uri(&release.uri);
-        let file_path = common::cache_path_from_uri(&uri);
-        let mut req_builder = self.get_request(client, uri)?;
-
-        // Set the username and password.
-        if let Some(ref username) = remote.username {
-            req_builder = req_builder.basic_auth(username, remote.password.as_ref());
-        }
-
-        let (checksum, algorithm) =
-            common::find_best_checksum(&release.checksums).ok_or(Error::ReleaseWithoutChecksums)?;
-
-        // Closure for downloading the firmware to our file, and then validating that it is correct.
-        let download_and_verify = |mut file: &mut File| {
-            info!("downloading firmware for {} ({})...", device.name, release.version);
-            if let Some(ref mut cb) = callback {
-                cb(FlashEvent::DownloadInitiate(release.size));
-            }
-
-            let mut response = req_builder
-                .send()
-                .map_err(Error::FirmwareGet)?
-                .error_for_status()
-                .map_err(Error::FirmwareGet)?;
-
-            let result = match callback {
-                Some(ref mut callback) => {
-                    let mut writer = ProgressWriter::new(&mut file, |progress| {
-                        callback(FlashEvent::DownloadUpdate(progress))
-                    });
-
-                    let result = io::copy(&mut response, &mut writer);
-                    callback(FlashEvent::DownloadComplete);
-                    result
-                }
-                None => io::copy(&mut response, file),
-            };
-
-            result.map_err(Error::FirmwareCopy)?;
-
-            file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
-
-            if let Some(ref mut cb) = callback {
-                cb(FlashEvent::VerifyingChecksum);
-            }
-
-            info!("validating firmware for {} ({})", device.name, release.version);
-            let checksum_matched = common::validate_checksum(file, checksum, algorithm)
-                .map_err(Error::FirmwareRead)?;
-
-            if !checksum_matched {
-                return Err(Error::FirmwareChecksumMismatch);
-            }
-
-            Ok(())
-        };
-
-        let mut file = None;
-
-        // If the firmware does not exist, or the checksum is invalid, it will need to be fetched.
-        let firmware_requires_fetching = if file_path.exists() {
-            info!("validating firmware for {} ({})", device.name, release.version);
-            let mut cache =
-                OpenOptions::new().read(true).open(&file_path).map_err(Error::FirmwareOpen)?;
-
-            let result =
-                !common::validate_checksum(&mut cache, checksum, algorithm).unwrap_or(false);
-
-            file = Some(cache);
-            result
-        } else {
-            true
-        };
-
-        if firmware_requires_fetching {
-            let mut download = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(&file_path)
-                .map_err(Error::FirmwareCreate)?;
-
-            // If any error occurs when downloading or verifying, delete the file that we created.
-            if let Err(why) = download_and_verify(&mut download) {
-                drop(download);
-                let _ = fs::remove_file(&file_path);
-                return Err(why);
-            }
-
-            file = Some(download);
-        }
-
-        if let Some(ref mut file) = file {
-            file.seek(SeekFrom::Start(0)).map_err(Error::FirmwareSeek)?;
-        }
-
-        Ok((file_path, file))
+        let cache_dir = common::cache_dir();
+        self.user_agent(|user_agent| {
+            firmware::fetch(client, user_agent, &cache_dir, &remote, device, release, callback)
+        })
     }
 
     /// Update firmware for a `Device` with the firmware specified in a `Release`.
     pub fn update_device_with_release<F: FnMut(FlashEvent)>(
         &self,
-        client: &HttpClient,
+        client: &dyn HttpTransport,
         device: &Device,
         release: &Release,
         mut flags: InstallFlags,
@@ -357,9 +476,41 @@ impl Client {
         self.install(device, "(user)", &filename, file, flags)
     }
 
+    /// Gets both the approved and blocked firmware lists.
+    pub fn firmware_policy(&self) -> Result<FirmwarePolicy, Error> {
+        let approved = self.approved_firmware()?;
+        let blocked = self.blocked_firmware()?;
+        Ok(FirmwarePolicy { approved, blocked })
+    }
+
     /// Gets a list of all the past firmware updates.
     pub fn history<H: IntoRawFd>(&self, handle: H) -> Result<Vec<Device>, Error> {
-        self.get_handle_method("GetHistory", handle)
+        decode_entries("GetHistory", self.get_handle_method("GetHistory", handle)?)
+    }
+
+    /// The best known configuration of the host, if one is set.
+    pub fn host_bkc(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostBkc").map(Box::from)
+    }
+
+    /// The machine ID of the host.
+    pub fn host_machine_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostMachineId").map(Box::from)
+    }
+
+    /// The product name of the host.
+    pub fn host_product(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostProduct").map(Box::from)
+    }
+
+    /// Gets the host security attributes, which were tested by the plugins of the daemon.
+    pub fn host_security_attrs(&self) -> Result<Vec<SecurityAttr>, Error> {
+        self.get_method("GetHostSecurityAttrs")
+    }
+
+    /// The host security ID that was calculated by the daemon, such as `HSI:2!`.
+    pub fn host_security_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostSecurityId").map(Box::from)
     }
 
     /// Schedules a firmware to be installed.
@@ -382,28 +533,7 @@ impl Client {
                 .into_raw_fd(),
         };
 
-        let filename = filename.as_os_str().to_str().expect("filename is not UTF-8");
-
-        let options: HashMap<&str, DynVariant> = cascade! {
-            opts: HashMap::new();
-            ..insert("reason", Variant(Box::new(reason.to_owned()) as Box<dyn RefArg>));
-            ..insert("filename", Variant(Box::new(filename.to_owned()) as Box<dyn RefArg>));
-            | if flags.contains(InstallFlags::OFFLINE) {
-                opts.insert("offline", Variant(Box::new(true) as Box<dyn RefArg>));
-            };
-            | if flags.contains(InstallFlags::ALLOW_OLDER) {
-                opts.insert("allow-older", Variant(Box::new(true) as Box<dyn RefArg>));
-            };
-            | if flags.contains(InstallFlags::ALLOW_REINSTALL) {
-                opts.insert("allow-reinstall", Variant(Box::new(true) as Box<dyn RefArg>));
-            };
-            | if flags.contains(InstallFlags::FORCE) {
-                opts.insert("force", Variant(Box::new(true) as Box<dyn RefArg>));
-            };
-            | if flags.contains(InstallFlags::NO_HISTORY) {
-                opts.insert("no-history", Variant(Box::new(true) as Box<dyn RefArg>));
-            };
-        };
+        let options = install_options(reason, filename, flags);
 
         let id: &str = id.as_ref().as_ref();
         let cb = |m: Message| m.append3(id, unsafe { OwnedFd::new(fd) }, options);
@@ -412,7 +542,12 @@ impl Client {
         Ok(())
     }
 
+    /// If the daemon is able to interact with the user, such as to show a prompt.
+    pub fn interactive(&self) -> Result<bool, Error> { self.get_property::<bool>("Interactive") }
+
     /// Listens for signals from the DBus daemon.
+    ///
+    /// The iterator ends once `cancellable` has been set to `true`.
     pub fn listen_signals<'a>(
         &'a self,
         cancellable: Arc<AtomicBool>,
@@ -425,48 +560,27 @@ impl Client {
             }
         }
 
-        fn read_signal<T: FromIterator<DBusEntry>>(
-            signal: Message,
-            method: &'static str,
-        ) -> Result<T, Error> {
-            let iter: Dict<String, Variant<Box<dyn RefArg + 'static>>, _> =
-                signal.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
-
-            Ok(T::from_iter(iter))
+        let rules = [
+            format!(
+                "type='signal',sender='{}',interface='{}',path='{}'",
+                self.bus_name, DBUS_IFACE, self.path
+            ),
+            format!(
+                "type='signal',sender='{}',interface='{}',member='PropertiesChanged',path='{}'",
+                self.bus_name, PROPERTIES_IFACE, self.path
+            ),
+        ];
+
+        for rule in &rules {
+            if let Err(why) = self.add_match(rule) {
+                error!("{}", Error::AddMatch(why));
+            }
         }
 
-        self.iter(TIMEOUT)
-            .take_while(move |_| cancellable.load(Ordering::SeqCst))
+        self.iter(SIGNAL_POLL_TIMEOUT)
+            .take_while(move |_| !cancellable.load(Ordering::SeqCst))
             .filter_map(filter_signal)
-            .filter_map(|signal| {
-                let signal = match &*signal.member().unwrap() {
-                    "Changed" => Ok(Signal::Changed),
-                    "DeviceAdded" => read_signal(signal, "DeviceAdded").map(Signal::DeviceAdded),
-                    "DeviceChanged" => {
-                        read_signal(signal, "DeviceChanged").map(Signal::DeviceChanged)
-                    }
-                    "DeviceRemoved" => {
-                        read_signal(signal, "DeviceRemoved").map(Signal::DeviceRemoved)
-                    }
-                    "PropertiesChanged" => signal
-                        .read3::<String, HashMap<String, DynVariant>, Vec<String>>()
-                        .map_err(|why| Error::ArgumentMismatch("PropertiesChanged", why))
-                        .map(|values| Signal::PropertiesChanged {
-                            interface:   values.0.into(),
-                            changed:     values.1,
-                            invalidated: values.2,
-                        }),
-                    _ => return None,
-                };
-
-                match signal {
-                    Ok(signal) => Some(signal),
-                    Err(why) => {
-                        eprintln!("signal error: {}", why);
-                        None
-                    }
-                }
-            })
+            .flat_map(signal::parse)
     }
 
     /// Modifies a device in some way.
@@ -493,6 +607,9 @@ impl Client {
         Ok(())
     }
 
+    /// If the daemon only allows firmware with trusted payloads to be installed.
+    pub fn only_trusted(&self) -> Result<bool, Error> { self.get_property::<bool>("OnlyTrusted") }
+
     /// The job percentage completion, or 0 for unknown.
     pub fn percentage(&self) -> Result<u8, Error> {
         self.get_property::<u32>("Percentage").map(|v| v as u8)
@@ -520,8 +637,30 @@ impl Client {
     pub fn results<D: AsRef<DeviceId>>(&self, id: D) -> Result<Option<Device>, Error> {
         let id: &str = id.as_ref().as_ref();
         let message = self.call_method("GetResults", |m| m.append1(id))?;
-        let iter: Option<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>> = message.get1();
-        Ok(iter.map(Device::from_iter))
+        message
+            .get1::<DBusEntries>()
+            .map(Device::try_from)
+            .transpose()
+            .map_err(|why| Error::Decode("GetResults", why))
+    }
+
+    /// Replaces the checksums of firmware which have been approved for installation.
+    pub fn set_approved_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        self.set_checksums("SetApprovedFirmware", checksums)
+    }
+
+    /// Replaces the checksums of firmware which may never be installed.
+    pub fn set_blocked_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        self.set_checksums("SetBlockedFirmware", checksums)
+    }
+
+    /// Tells the daemon which features this client supports.
+    ///
+    /// The daemon only sends requests, and only allows actions such as switching branches, to
+    /// clients which have declared support for them.
+    pub fn set_feature_flags(&self, flags: FeatureFlags) -> Result<(), Error> {
+        self.call_method("SetFeatureFlags", |m| m.append1(flags.bits()))?;
+        Ok(())
     }
 
     /// The daemon status, e.g. `Decompressing`.
@@ -529,6 +668,23 @@ impl Client {
         self.get_property::<u32>("Status").map(|v| Status::from(v as u8))
     }
 
+    /// Installs a release from a different branch than the firmware installed on the device.
+    ///
+    /// The daemon only allows switching branches for clients which have declared
+    /// `FeatureFlags::SWITCH_BRANCH`. A release which is older than the installed firmware is
+    /// refused, unless `flags` also contains `InstallFlags::ALLOW_OLDER`.
+    pub fn switch_branch<F: FnMut(FlashEvent)>(
+        &self,
+        client: &dyn HttpTransport,
+        device: &Device,
+        release: &Release,
+        flags: InstallFlags,
+        callback: Option<F>,
+    ) -> Result<(), Error> {
+        let flags = flags | InstallFlags::ALLOW_BRANCH_SWITCH;
+        self.update_device_with_release(client, device, release, flags, callback)
+    }
+
     /// If the daemon has been tainted with a third party plugin.
     pub fn tainted(&self) -> Result<bool, Error> { self.get_property::<bool>("Tainted") }
 
@@ -579,57 +735,60 @@ impl Client {
     }
 
     /// Convenience method for creating a GET request with the proper user agent.
-    fn get_request(
+    fn get_request(&self, uri: Url) -> Result<HttpRequest, Error> {
+        self.user_agent(|user_agent| Ok(firmware::get_request(user_agent, uri)))
+    }
+
+    fn get_checksums(&self, method: &'static str) -> Result<Vec<Box<str>>, Error> {
+        let message = self.call_method(method, |m| m)?;
+        let checksums: Vec<String> =
+            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
+
+        Ok(checksums.into_iter().map(Box::from).collect())
+    }
+
+    fn set_checksums<S: AsRef<str>>(
         &self,
-        client: &HttpClient,
-        uri: impl IntoUrl,
-    ) -> Result<reqwest::blocking::RequestBuilder, Error> {
-        self.user_agent(|user_agent| {
-            Ok(client.get(uri).header(USER_AGENT, HeaderValue::from_str(user_agent).unwrap()))
-        })
+        method: &'static str,
+        checksums: &[S],
+    ) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method(method, |m| m.append1(checksums))?;
+        Ok(())
     }
 
-    fn get_method<T: FromIterator<DBusEntry>>(
+    fn get_method<T: TryFrom<DBusEntries, Error = DecodeError>>(
         &self,
         method: &'static str,
     ) -> Result<Vec<T>, Error> {
         let message = self.call_method(method, |m| m)?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
-
-        Ok(iter.map(T::from_iter).collect())
+        decode_entries(method, read_entries(method, &message)?)
     }
 
-    fn get_device_method<T: FromIterator<DBusEntry>, C: FromIterator<T>>(
+    fn get_device_method<T: TryFrom<DBusEntries, Error = DecodeError>>(
         &self,
         method: &'static str,
         device_id: &str,
-    ) -> Result<C, Error> {
+    ) -> Result<Vec<T>, Error> {
         let message = self.call_method(method, |m| m.append1(device_id))?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
-
-        Ok(C::from_iter(iter.map(T::from_iter)))
+        decode_entries(method, read_entries(method, &message)?)
     }
 
-    fn get_handle_method<T: FromIterator<DBusEntry>, H: IntoRawFd>(
+    fn get_handle_method<H: IntoRawFd>(
         &self,
         method: &'static str,
         handle: H,
-    ) -> Result<Vec<T>, Error> {
+    ) -> Result<Vec<DBusEntries>, Error> {
         let cb = move |m: Message| m.append1(unsafe { OwnedFd::new(handle.into_raw_fd()) });
 
         let message = self.call_method(method, cb)?;
-        let iter: Array<Dict<String, Variant<Box<dyn RefArg + 'static>>, _>, _> =
-            message.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
-
-        Ok(iter.map(T::from_iter).collect())
+        read_entries(method, &message)
     }
 
     fn get_property<T: for<'a> Get<'a> + Arg>(&self, property: &'static str) -> Result<T, Error> {
-        self.connection_path()
-            .get::<T>(DBUS_NAME, property)
-            .map_err(|why| Error::GetProperty(property, why))
+        self.timed(property, property_error, || {
+            self.connection_path().get::<T>(DBUS_IFACE, property)
+        })
     }
 
     fn call_method<F: FnOnce(Message) -> Message>(
@@ -637,23 +796,87 @@ impl Client {
         method: &'static str,
         append_args: F,
     ) -> Result<Message, Error> {
-        let mut m = Message::new_method_call(DBUS_NAME, DBUS_PATH, DBUS_IFACE, method)
+        let mut m = Message::new_method_call(&*self.bus_name, &*self.path, DBUS_IFACE, method)
             .map_err(|why| Error::NewMethodCall(method, why))?;
 
         m = append_args(m);
 
-        self.send_with_reply_and_block(m, TIMEOUT).map_err(|why| Error::Call(method, why))
+        match self.cancellable {
+            Some(ref cancellable) => self.call_cancellable(method, m, cancellable),
+            None => self.timed(method, call_error, || {
+                self.send_with_reply_and_block(m, self.timeout_ms())
+            }),
+        }
+    }
+
+    /// Makes a call which waits for its reply until the timeout, converting its error.
+    ///
+    /// libdbus fails with `NoReply` both when the timeout elapses, and when the daemon leaves
+    /// the bus without replying, so the time that was waited tells them apart.
+    fn timed<T, F: FnOnce() -> Result<T, dbus::Error>>(
+        &self,
+        name: &'static str,
+        error: fn(&'static str, dbus::Error) -> Error,
+        call: F,
+    ) -> Result<T, Error> {
+        let start = Instant::now();
+        call().map_err(|why| {
+            let expired = self.timeout().map_or(false, |timeout| start.elapsed() >= timeout);
+            if expired && why.name() == Some("org.freedesktop.DBus.Error.NoReply") {
+                Error::Timeout(name)
+            } else {
+                error(name, why)
+            }
+        })
+    }
+
+    /// Sends the method call, and polls for its reply until it arrives, times out, or is
+    /// cancelled.
+    fn call_cancellable(
+        &self,
+        method: &'static str,
+        message: Message,
+        cancellable: &AtomicBool,
+    ) -> Result<Message, Error> {
+        let deadline = self.timeout().map(|timeout| Instant::now() + timeout);
+        let serial = self.send(message).map_err(|()| Error::Send(method))?;
+
+        loop {
+            if cancellable.load(Ordering::SeqCst) {
+                return Err(Error::Cancelled(method));
+            }
+
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(Error::Timeout(method));
+            }
+
+            for mut reply in self.incoming(CANCEL_POLL_TIMEOUT) {
+                if reply.get_reply_serial() == Some(serial) {
+                    if let Err(why) = reply.as_result() {
+                        return Err(call_error(method, why));
+                    }
+
+                    return Ok(reply);
+                }
+            }
+        }
     }
 
     fn connection_path(&self) -> ConnPath<&Connection> {
-        self.with_path(DBUS_NAME, DBUS_PATH, TIMEOUT)
+        self.with_path(&*self.bus_name, &*self.path, self.timeout_ms())
+    }
+
+    fn timeout_ms(&self) -> i32 {
+        self.timeout().map_or(TIMEOUT, |timeout| {
+            timeout.as_millis().min(i32::max_value() as u128) as i32
+        })
     }
 
     /// Fetch and cache the user agent in a thread-safe manner.
     fn user_agent<T, F: FnOnce(&str) -> Result<T, Error>>(&self, func: F) -> Result<T, Error> {
-        let lock = self.user_agent.read().unwrap();
+        let cached = self.user_agent.borrow();
 
-        let user_agent: Cow<str> = match *lock {
+        let user_agent: Cow<str> = match *cached {
             Some(ref agent) => Cow::Borrowed(agent.as_ref()),
             None => Cow::Owned(["fwupd/", &*self.daemon_version()?].concat().into()),
         };
@@ -661,32 +884,98 @@ impl Client {
         let value = func(&user_agent)?;
 
         if let Cow::Owned(user_agent) = user_agent {
-            drop(lock);
-            *self.user_agent.write().unwrap() = Some(user_agent.into());
+            drop(cached);
+            *self.user_agent.borrow_mut() = Some(user_agent.into());
         }
 
         Ok(value)
     }
 }
 
-/// Signal received by the daemon when listening for signal events with `Client::listen_signals()`.
-pub enum Signal {
-    /// Some value on the interface or the number of devices or profiles has changed.
-    Changed,
-    /// A device has been added.
-    DeviceAdded(Device),
-    /// A device has been changed.
-    DeviceChanged(Device),
-    /// A device has been removed.
-    DeviceRemoved(Device),
-    /// Triggers when a property has changed.
-    PropertiesChanged {
-        interface:   Box<str>,
-        changed:     HashMap<String, DynVariant>,
-        invalidated: Vec<String>,
-    },
+/// Converts the error of a failed method call, separating timeouts and errors replied by the
+/// daemon from other failures.
+pub(crate) fn call_error(method: &'static str, why: dbus::Error) -> Error {
+    if let Some(error) = FwupdError::from_dbus(&why) {
+        Error::Fwupd(method, error)
+    } else if is_timeout(&why) {
+        Error::Timeout(method)
+    } else {
+        Error::Call(method, why)
+    }
+}
+
+/// Converts the error of a failed property read, in the same manner as `call_error`.
+pub(crate) fn property_error(property: &'static str, why: dbus::Error) -> Error {
+    if let Some(error) = FwupdError::from_dbus(&why) {
+        Error::Fwupd(property, error)
+    } else if is_timeout(&why) {
+        Error::Timeout(property)
+    } else {
+        Error::GetProperty(property, why)
+    }
+}
+
+fn is_timeout(why: &dbus::Error) -> bool {
+    match why.name() {
+        Some("org.freedesktop.DBus.Error.Timeout")
+        | Some("org.freedesktop.DBus.Error.TimedOut") => true,
+        _ => false,
+    }
+}
+
+/// Reads the array of dictionaries returned by a method.
+pub(crate) fn read_entries(
+    method: &'static str,
+    message: &Message,
+) -> Result<Vec<DBusEntries>, Error> {
+    message.read1().map_err(|why| Error::ArgumentMismatch(method, why))
+}
+
+/// Decodes each dictionary of entries returned by a method.
+pub(crate) fn decode_entries<T: TryFrom<DBusEntries, Error = DecodeError>>(
+    method: &'static str,
+    entries: Vec<DBusEntries>,
+) -> Result<Vec<T>, Error> {
+    entries
+        .into_iter()
+        .map(|entries| T::try_from(entries).map_err(|why| Error::Decode(method, why)))
+        .collect()
+}
+
+/// Creates the options dictionary passed to the daemon's `Install` method.
+pub(crate) fn install_options(
+    reason: &str,
+    filename: &Path,
+    flags: InstallFlags,
+) -> HashMap<&'static str, DynVariant> {
+    let filename = filename.as_os_str().to_str().expect("filename is not UTF-8");
+
+    cascade! {
+        opts: HashMap::new();
+        ..insert("reason", Variant(Box::new(reason.to_owned()) as Box<dyn RefArg>));
+        ..insert("filename", Variant(Box::new(filename.to_owned()) as Box<dyn RefArg>));
+        | for &(flag, option) in INSTALL_OPTIONS {
+            if flags.contains(flag) {
+                opts.insert(option, Variant(Box::new(true) as Box<dyn RefArg>));
+            }
+        };
+    }
 }
 
+/// The option that the daemon's `Install` method accepts for each install flag.
+const INSTALL_OPTIONS: &[(InstallFlags, &str)] = &[
+    (InstallFlags::OFFLINE, "offline"),
+    (InstallFlags::ALLOW_REINSTALL, "allow-reinstall"),
+    (InstallFlags::ALLOW_OLDER, "allow-older"),
+    (InstallFlags::FORCE, "force"),
+    (InstallFlags::NO_HISTORY, "no-history"),
+    (InstallFlags::ALLOW_BRANCH_SWITCH, "allow-branch-switch"),
+    (InstallFlags::IGNORE_CHECKSUM, "ignore-checksum"),
+    (InstallFlags::IGNORE_VID_PID, "ignore-vid-pid"),
+    (InstallFlags::IGNORE_POWER, "ignore-power"),
+    (InstallFlags::NO_SEARCH, "no-search"),
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -715,17 +1004,694 @@ mod tests {
     #[test]
     fn remote_baseuri() {
         let remote = download_remote();
-        let firmware_uri = remote.firmware_uri("http://bbc.co.uk/firmware.cab");
+        let firmware_uri = remote.firmware_uri("http://bbc.co.uk/firmware.cab").unwrap();
         assert_eq!(firmware_uri.to_string().as_str(), "https://my.fancy.cdn/firmware.cab")
     }
 
     #[test]
     fn remote_nopath() {
         let remote = nopath_remote();
-        let firmware_uri = remote.firmware_uri("firmware.cab");
+        let firmware_uri = remote.firmware_uri("firmware.cab").unwrap();
         assert_eq!(
             firmware_uri.to_string().as_str(),
             "https://s3.amazonaws.com/lvfsbucket/downloads/firmware.cab"
         )
     }
+
+    #[test]
+    fn release_version_ordering() {
+        let release = |version: &str| Release { version: version.into(), ..Default::default() };
+
+        let mut releases = vec![release("1.10.0"), release("1.9.0"), release("1.9.1a")];
+        releases.sort();
+
+        let versions: Vec<&str> = releases.iter().map(|r| r.version.as_ref()).collect();
+        assert_eq!(versions, ["1.9.0", "1.9.1a", "1.10.0"]);
+        assert!(release("1.2.3") == release("1.2.3"));
+        assert!(release("1.2.3") < release("1.2.3.1"));
+
+        // Suffixes are compared case-sensitively, so that equality agrees with the ordering.
+        assert!(release("1.2.3a") != release("1.2.3A"));
+        assert!(release("1.2.3A") < release("1.2.3a"));
+    }
+
+    #[test]
+    fn version_formats() {
+        let device = |format: VersionFormat| Device {
+            version: "0x01020003".into(),
+            version_format: Some(format),
+            ..Default::default()
+        };
+
+        assert_eq!(device(VersionFormat::Triplet).parsed_version().normalized(), "1.2.3");
+        assert_eq!(device(VersionFormat::Quad).parsed_version().normalized(), "1.2.0.3");
+        assert_eq!(device(VersionFormat::Pair).parsed_version().normalized(), "258.3");
+        assert_eq!(device(VersionFormat::Unknown).parsed_version().normalized(), "0x01020003");
+
+        let date = Device { version: "20200412".into(), ..device(VersionFormat::Triplet) };
+        assert_eq!(date.parsed_version().normalized(), "20200412");
+        let signed = Device { version: "+16".into(), ..device(VersionFormat::Triplet) };
+        assert_eq!(signed.parsed_version().normalized(), "+16");
+
+        let device = device(VersionFormat::Triplet);
+        assert!(device.parse_version("1.2.4") > device.parsed_version());
+        assert!(device.parse_version("0x01020003") == Version::from("1.2.3"));
+    }
+
+    #[test]
+    fn vercmp_fwupd_cases() {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+
+        // The cases of the vercmp self test of fwupd, and plain integers which it parses as
+        // triplets.
+        let cases = [
+            ("1.2.3", "1.2.3", Equal),
+            ("001.002.003", "001.002.003", Equal),
+            ("1.2.3", "0x1020003", Equal),
+            ("0x10203", "0x10203", Equal),
+            ("1.2.3", "1.2.4", Less),
+            ("001.002.000", "001.002.009", Less),
+            ("1.2.3", "1.2.2", Greater),
+            ("001.002.009", "001.002.000", Greater),
+            ("1.2.3", "1.2.3.1", Less),
+            ("1.2.3.1", "1.2.4", Less),
+            ("1.2.3a", "1.2.3a", Equal),
+            ("1.2.3a", "1.2.3b", Less),
+            ("1.2.3b", "1.2.3a", Greater),
+            ("1.2.3", "1.2.3a", Less),
+            ("1.2.3a", "1.2.3", Greater),
+            ("alpha", "alpha", Equal),
+            ("alpha", "beta", Less),
+            ("beta", "alpha", Greater),
+            ("1.2a.3", "1.2a.3", Equal),
+            ("1.2a.3", "1.2b.3", Less),
+            ("1.2b.3", "1.2a.3", Greater),
+            ("65536", "0.1.0", Equal),
+            ("65536", "1.0.0", Less),
+        ];
+
+        let release = |version: &str| Release { version: version.into(), ..Default::default() };
+        for &(a, b, ordering) in &cases {
+            assert_eq!(Version::from(a).cmp(&Version::from(b)), ordering, "{} <=> {}", a, b);
+            assert_eq!(release(a).cmp(&release(b)), ordering, "{} <=> {}", a, b);
+        }
+
+        let device = Device { version: "65536".into(), ..Default::default() };
+        assert_eq!(device.parsed_version().normalized(), "0.1.0");
+    }
+
+    #[test]
+    fn builder_validation() {
+        match ClientBuilder::new().bus_name("org..fwupd").build() {
+            Err(Error::InvalidBusName(_)) => (),
+            other => panic!("expected an invalid bus name, found {:?}", other.err()),
+        }
+
+        match ClientBuilder::new().path("/org/freedesktop/").build() {
+            Err(Error::InvalidPath(_)) => (),
+            other => panic!("expected an invalid path, found {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn fwupd_error_names() {
+        use FwupdErrorKind::*;
+
+        let kinds = [
+            ("Internal", Internal),
+            ("VersionNewer", VersionNewer),
+            ("VersionSame", VersionSame),
+            ("AlreadyPending", AlreadyPending),
+            ("AuthFailed", AuthFailed),
+            ("Read", Read),
+            ("Write", Write),
+            ("InvalidFile", InvalidFile),
+            ("NotFound", NotFound),
+            ("NothingToDo", NothingToDo),
+            ("NotSupported", NotSupported),
+            ("SignatureInvalid", SignatureInvalid),
+            ("AcPowerRequired", AcPowerRequired),
+            ("PermissionDenied", PermissionDenied),
+            ("BrokenSystem", BrokenSystem),
+            ("BatteryLevelTooLow", BatteryLevelTooLow),
+            ("NeedsUserAction", NeedsUserAction),
+        ];
+
+        for &(name, kind) in &kinds {
+            let full = ["org.freedesktop.fwupd.", name].concat();
+            assert_eq!(FwupdErrorKind::from_name(&full), Some(kind), "{}", full);
+            assert_eq!(kind.name(), name);
+        }
+
+        assert_eq!(FwupdErrorKind::from_name("org.freedesktop.fwupd.Unknown"), None);
+        assert_eq!(FwupdErrorKind::from_name("NothingToDo"), None);
+        assert_eq!(FwupdErrorKind::from_name("org.freedesktop.DBus.Error.NoReply"), None);
+    }
+
+    #[test]
+    fn host_security_id() {
+        let attr = |hsi_level: u32, flags: SecurityAttrFlags| SecurityAttr {
+            hsi_level,
+            flags,
+            ..Default::default()
+        };
+
+        let mut attrs = vec![
+            attr(1, SecurityAttrFlags::SUCCESS),
+            attr(1, SecurityAttrFlags::OBSOLETED),
+            attr(2, SecurityAttrFlags::SUCCESS),
+            attr(3, SecurityAttrFlags::empty()),
+            attr(4, SecurityAttrFlags::SUCCESS),
+        ];
+
+        assert_eq!(HostSecurityId::calculate(&attrs).to_string(), "HSI:2");
+
+        attrs.push(attr(0, SecurityAttrFlags::RUNTIME_ISSUE));
+        attrs.push(attr(0, SecurityAttrFlags::RUNTIME_UPDATES | SecurityAttrFlags::SUCCESS));
+        assert_eq!(HostSecurityId::calculate(&attrs).to_string(), "HSI:2!+");
+
+        assert_eq!(HostSecurityId::calculate(&[]).to_string(), "HSI:0");
+    }
+
+    #[test]
+    fn install_options_flags() {
+        let filename = Path::new("/tmp/firmware.cab");
+
+        let options = install_options("(user)", filename, InstallFlags::empty());
+        let mut keys: Vec<&str> = options.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["filename", "reason"]);
+        assert_eq!(options["filename"].0.as_str(), Some("/tmp/firmware.cab"));
+
+        for &(flag, option) in INSTALL_OPTIONS {
+            let options = install_options("(user)", filename, flag);
+            assert_eq!(options.len(), 3, "{:?} produced extra options", flag);
+            assert_eq!(&*options[option].0.signature(), "b");
+            assert_eq!(options[option].0.as_u64(), Some(1), "{} was not set", option);
+        }
+
+        assert_eq!(install_options("(user)", filename, InstallFlags::all()).len(), 12);
+    }
+
+    #[test]
+    fn release_locations() {
+        let mut release = Release { uri: "https://example.com/a.cab".into(), ..Default::default() };
+        assert_eq!(release.locations(), ["https://example.com/a.cab"]);
+
+        release.locations =
+            vec!["https://mirror.example.com/a.cab".into(), "https://example.com/a.cab".into()]
+                .into_boxed_slice();
+        assert_eq!(release.locations(), [
+            "https://mirror.example.com/a.cab",
+            "https://example.com/a.cab"
+        ]);
+    }
+
+    fn entries(pairs: Vec<(&str, Box<dyn RefArg>)>) -> DBusEntries {
+        pairs.into_iter().map(|(key, value)| (key.to_owned(), Variant(value))).collect()
+    }
+
+    #[test]
+    fn decode_mistyped_entries() {
+        let why = Device::try_from(entries(vec![
+            ("DeviceId", Box::new("mock-device".to_owned())),
+            ("Flags", Box::new("updatable".to_owned())),
+        ]))
+        .unwrap_err();
+        assert_eq!(&*why.field, "Flags");
+        assert_eq!(why.expected, "one of b, y, q, u or t");
+        assert_eq!(&*why.found, "s");
+
+        let why = Release::try_from(entries(vec![("Version", Box::new(1u32))])).unwrap_err();
+        assert_eq!((&*why.field, &*why.found), ("Version", "u"));
+        assert_eq!(why.expected, "one of s, o or g");
+
+        let why = Remote::try_from(entries(vec![("Priority", Box::new(1.5f64))])).unwrap_err();
+        assert_eq!((&*why.field, &*why.found), ("Priority", "d"));
+        assert_eq!(why.expected, "one of b, y, n, q, i, u or x");
+
+        // Narrower integers than the field are accepted.
+        let device = Device::try_from(entries(vec![("Flags", Box::new(2u32))])).unwrap();
+        assert_eq!(device.flags, DeviceFlags::from_bits_truncate(2));
+    }
+
+    #[test]
+    fn decode_unknown_keys() {
+        let unknown = || {
+            entries(vec![
+                ("Name", Box::new("Mock".to_owned())),
+                ("FutureKey", Box::new(7u32)),
+                ("FutureList", Box::new(vec!["a".to_owned(), "b".to_owned()])),
+            ])
+        };
+
+        let list = Value::Array(vec![Value::Str("a".into()), Value::Str("b".into())]);
+
+        let device = Device::try_from(unknown()).unwrap();
+        assert_eq!(&*device.name, "Mock");
+        assert_eq!(device.extra.len(), 2);
+        assert_eq!(device.extra.get("FutureKey"), Some(&Value::UInt32(7)));
+        assert_eq!(device.extra.get("FutureList"), Some(&list));
+
+        let release = Release::try_from(unknown()).unwrap();
+        assert_eq!(release.extra.get("FutureKey"), Some(&Value::UInt32(7)));
+        assert_eq!(release.extra.get("FutureList"), Some(&list));
+
+        let remote = Remote::try_from(unknown()).unwrap();
+        assert_eq!(remote.extra.get("FutureKey"), Some(&Value::UInt32(7)));
+        assert!(remote.extra.contains_key("Name"));
+    }
+
+    #[test]
+    fn signal_parse() {
+        use crate::signal::parse;
+
+        let properties_changed = |interface: &str, changed: DBusEntries, invalidated: &[&str]| {
+            let invalidated: Vec<String> = invalidated.iter().map(|&p| p.to_owned()).collect();
+            Message::new_signal(DBUS_PATH, PROPERTIES_IFACE, "PropertiesChanged")
+                .unwrap()
+                .append3(interface, changed, invalidated)
+        };
+
+        let changed: DBusEntries = cascade! {
+            HashMap::new();
+            ..insert("Status".into(), Variant(Box::new(3u32) as Box<dyn RefArg>));
+            ..insert("HostBkc".into(), Variant(Box::new("bkc".to_owned()) as Box<dyn RefArg>));
+        };
+
+        let signals = parse(properties_changed(DBUS_IFACE, changed, &["Tainted"]));
+        assert_eq!(signals.len(), 3);
+        assert!(signals.iter().any(|s| match s {
+            Signal::StatusChanged(Status::Decompressing) => true,
+            _ => false,
+        }));
+        assert!(signals.iter().any(|s| match s {
+            Signal::PropertyChanged(name, Value::Str(value)) => {
+                &**name == "HostBkc" && &**value == "bkc"
+            }
+            _ => false,
+        }));
+        match signals.last() {
+            Some(Signal::PropertyInvalidated(name)) => assert_eq!(&**name, "Tainted"),
+            other => panic!("expected PropertyInvalidated, found {:?}", other),
+        }
+
+        let other = properties_changed("org.example.Other", HashMap::new(), &["Tainted"]);
+        assert!(parse(other).is_empty());
+
+        let changed = Message::new_signal(DBUS_PATH, DBUS_IFACE, "Changed").unwrap();
+        match parse(changed).as_slice() {
+            [Signal::Changed] => (),
+            other => panic!("expected Changed, found {:?}", other),
+        }
+
+        let mistyped =
+            Message::new_signal(DBUS_PATH, DBUS_IFACE, "DeviceAdded").unwrap().append1(1u32);
+        assert!(parse(mistyped).is_empty());
+    }
+
+    #[cfg(feature = "mock")]
+    mod mock {
+        use super::*;
+        use crate::{common::temp_dir, http::StubTransport, mock::MockDaemon};
+        use std::env;
+
+        fn daemon() -> MockDaemon { MockDaemon::private().expect("failed to start mock daemon") }
+
+        fn device() -> Device {
+            Device {
+                device_id: DeviceId::from("mock-device"),
+                flags: DeviceFlags::UPDATABLE,
+                name: "Mock Device".into(),
+                version: "1.2.3".into(),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn mock_install() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            let path = env::temp_dir().join("fwupd-dbus-mock-install.cab");
+            std::fs::write(&path, b"firmware").unwrap();
+
+            let device = device();
+            client
+                .install(
+                    &device,
+                    "(user)",
+                    &path,
+                    None::<File>,
+                    InstallFlags::OFFLINE | InstallFlags::ALLOW_OLDER,
+                )
+                .unwrap();
+
+            let installs = daemon.installs();
+            assert_eq!(installs.len(), 1);
+            assert_eq!(installs[0].device_id, device.device_id);
+            assert_eq!(installs[0].options.get("offline"), Some(&Value::Bool(true)));
+            assert_eq!(installs[0].options.get("allow-older"), Some(&Value::Bool(true)));
+            assert_eq!(installs[0].options.get("force"), None);
+        }
+
+        #[test]
+        fn mock_branch_releases() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+
+            let release = |version: &str, branch: Option<&str>| Release {
+                version: version.into(),
+                branch: branch.map(Box::from),
+                ..Default::default()
+            };
+
+            let device = device();
+            daemon.add_device(device.clone());
+            daemon.add_release(&device.device_id, release("1.3.0", None));
+            daemon.add_release(&device.device_id, release("4.12", Some("coreboot")));
+
+            let releases = client.branch_releases(&device).unwrap();
+            assert_eq!(releases.len(), 1);
+            assert_eq!(releases[0].branch.as_ref().map(AsRef::as_ref), Some("coreboot"));
+
+            let path = env::temp_dir().join("fwupd-dbus-mock-branch.cab");
+            std::fs::write(&path, b"firmware").unwrap();
+            let flags = InstallFlags::ALLOW_BRANCH_SWITCH;
+            client.install(&device, "(user)", &path, None::<File>, flags).unwrap();
+
+            let options = &daemon.installs()[0].options;
+            assert_eq!(options.get("allow-branch-switch"), Some(&Value::Bool(true)));
+        }
+
+        #[test]
+        fn mock_bus_name() {
+            let daemon = daemon();
+            let builder = ClientBuilder::new().bus(Bus::Address(daemon.address().into()));
+
+            assert!(builder.clone().build().unwrap().daemon_version().is_ok());
+            assert!(builder.bus_name("org.example.Missing").build().unwrap().devices().is_err());
+        }
+
+        #[test]
+        fn mock_blocked_releases() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+
+            let release = |version: &str, checksum: &str| Release {
+                version: version.into(),
+                checksums: vec![checksum.into()].into_boxed_slice(),
+                ..Default::default()
+            };
+
+            let device = device();
+            daemon.add_release(&device.device_id, release("1.0.0", "approved"));
+            daemon.add_release(&device.device_id, release("1.1.0", "blocked"));
+            daemon.add_release(&device.device_id, release("1.2.0", "unknown"));
+
+            client.set_approved_firmware(&["approved", "blocked"]).unwrap();
+            client.set_blocked_firmware(&["blocked"]).unwrap();
+            assert_eq!(client.blocked_firmware().unwrap(), vec![Box::<str>::from("blocked")]);
+
+            let blocked = client.blocked_releases(&device).unwrap();
+            let blocked: Vec<(&str, ReleaseFlags)> =
+                blocked.iter().map(|(release, flags)| (release.version.as_ref(), *flags)).collect();
+
+            let expected = [
+                ("1.1.0", ReleaseFlags::BLOCKED_VERSION),
+                ("1.2.0", ReleaseFlags::BLOCKED_APPROVAL),
+            ];
+
+            assert_eq!(blocked, expected);
+        }
+
+        #[test]
+        fn mock_cancellable() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            daemon.delay_method("Verify", Duration::from_secs(1));
+
+            let cancellable = Arc::new(AtomicBool::new(false));
+            let canceller = {
+                let cancellable = cancellable.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(200));
+                    cancellable.store(true, Ordering::SeqCst);
+                })
+            };
+
+            let start = Instant::now();
+            match client.with_cancellable(cancellable).verify(&device()) {
+                Err(Error::Cancelled("Verify")) => (),
+                other => panic!("expected the call to be cancelled, found {:?}", other),
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(1), "waited for the delayed reply");
+            canceller.join().unwrap();
+        }
+
+        #[test]
+        fn mock_security_attrs() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            daemon.add_security_attr(SecurityAttr {
+                appstream_id: "org.fwupd.hsi.Uefi.SecureBoot".into(),
+                flags: SecurityAttrFlags::SUCCESS,
+                hsi_level: 1,
+                result: SecurityAttrResult::Enabled,
+                ..Default::default()
+            });
+
+            let attrs = client.host_security_attrs().unwrap();
+            assert_eq!(attrs.len(), 1);
+            assert_eq!(&*attrs[0].appstream_id, "org.fwupd.hsi.Uefi.SecureBoot");
+            assert_eq!(attrs[0].result, SecurityAttrResult::Enabled);
+            assert_eq!(HostSecurityId::calculate(&attrs).to_string(), "HSI:1");
+        }
+
+        #[test]
+        fn mock_timeout() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            daemon.delay_method("GetDevices", Duration::from_secs(1));
+
+            let timeout = Some(Duration::from_millis(100));
+            match client.with_timeout(timeout).devices() {
+                Err(Error::Timeout("GetDevices")) => (),
+                other => panic!("expected the call to time out, found {:?}", other),
+            }
+
+            assert_eq!(client.timeout(), None);
+        }
+
+        #[test]
+        fn mock_feature_flags() {
+            let daemon = daemon();
+            let flags = FeatureFlags::REQUESTS | FeatureFlags::SWITCH_BRANCH;
+            let client = ClientBuilder::new()
+                .bus(Bus::Address(daemon.address().into()))
+                .feature_flags(flags)
+                .build()
+                .unwrap();
+
+            assert_eq!(daemon.feature_flags(), flags);
+
+            client.set_feature_flags(FeatureFlags::CAN_REPORT).unwrap();
+            assert_eq!(daemon.feature_flags(), FeatureFlags::CAN_REPORT);
+        }
+
+        #[test]
+        fn mock_properties() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            daemon.set_property("BatteryLevel", Value::UInt32(42));
+            daemon.set_property("Status", Value::UInt32(Status::Scheduling as u32));
+
+            let properties = client.daemon_properties().unwrap();
+            assert_eq!(properties.battery_level, Some(42));
+            assert_eq!(properties.battery_threshold, None);
+            assert_eq!(&*properties.host_product, "Mock Host");
+            assert_eq!(properties.status, Status::Scheduling);
+            assert!(properties.only_trusted);
+            assert!(properties.extra.is_empty());
+
+            assert_eq!(client.battery_level().unwrap(), Some(42));
+            assert_eq!(&*client.host_machine_id().unwrap(), "mock-machine-id");
+            assert!(!client.interactive().unwrap());
+        }
+
+        #[test]
+        fn mock_results() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+
+            let why = client.results(&device()).unwrap_err();
+            assert_eq!(why.fwupd_error().map(|e| e.kind), Some(FwupdErrorKind::NothingToDo));
+
+            daemon.set_results(Device { update_state: Some(UpdateState::Success), ..device() });
+            let results = client.results(&device()).unwrap().expect("no results");
+            assert_eq!(results.update_state, Some(UpdateState::Success));
+            assert_eq!(&*results.name, "Mock Device");
+        }
+
+        #[test]
+        fn mock_listen_signals() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            let cancellable = Arc::new(AtomicBool::new(false));
+            let mut signals = client.listen_signals(cancellable.clone());
+
+            daemon.emit(Signal::DeviceAdded(device()));
+            daemon.emit(Signal::StatusChanged(Status::Downloading));
+
+            match signals.next() {
+                Some(Signal::DeviceAdded(device)) => assert_eq!(&*device.name, "Mock Device"),
+                other => panic!("expected DeviceAdded, found {:?}", other),
+            }
+
+            match signals.next() {
+                Some(Signal::StatusChanged(status)) => assert_eq!(status, Status::Downloading),
+                other => panic!("expected StatusChanged, found {:?}", other),
+            }
+
+            daemon.emit(Signal::DeviceRequest(Request {
+                device_id: device().device_id,
+                id: RequestId::RemoveReplug.as_str().into(),
+                kind: RequestKind::Immediate,
+                ..Default::default()
+            }));
+
+            match signals.next() {
+                Some(Signal::DeviceRequest(request)) => {
+                    assert_eq!(request.well_known_id(), Some(RequestId::RemoveReplug));
+                    assert_eq!(request.kind, RequestKind::Immediate);
+                }
+                other => panic!("expected DeviceRequest, found {:?}", other),
+            }
+
+            assert_eq!(client.status().unwrap(), Status::Downloading);
+
+            cancellable.store(true, Ordering::SeqCst);
+            assert!(signals.next().is_none());
+        }
+
+        #[test]
+        fn mock_update_metadata_verified() {
+            let daemon = daemon();
+            let client = daemon.client().unwrap();
+            let http = StubTransport { body: b"<components/>", mirror: "cdn.fwupd-dbus.test" };
+            let remote = Remote {
+                enabled: true,
+                filename_cache: "firmware.xml.gz".into(),
+                keyring: KeyringKind::GPG,
+                remote_id: "fwupd-dbus-mock-verified".into(),
+                uri: Some("https://cdn.fwupd-dbus.test/firmware.xml.gz".into()),
+                ..Default::default()
+            };
+
+            let roots = TrustRoots::new(temp_dir("mock-trust-roots"));
+            let cache_dir = temp_dir("mock-metadata-cache");
+            match remote.update_metadata_with(&client, &http, Some(&roots), &cache_dir) {
+                Err(UpdateError::Signature(_)) => (),
+                other => panic!("expected the signature to be rejected, found {:?}", other),
+            }
+
+            assert!(daemon.metadata_updates().is_empty());
+
+            remote.update_metadata_with(&client, &http, None, &cache_dir).unwrap();
+            assert_eq!(daemon.metadata_updates(), [RemoteId::from("fwupd-dbus-mock-verified")]);
+            assert!(cache_dir.join("fwupd-dbus-mock-verified/firmware.xml.gz").exists());
+
+            let _ = std::fs::remove_dir_all(&roots.path);
+            let _ = std::fs::remove_dir_all(&cache_dir);
+        }
+
+        #[cfg(feature = "async")]
+        mod nonblock {
+            use super::*;
+            use futures::StreamExt;
+            use std::future::Future;
+
+            fn block_on<F: Future>(future: F) -> F::Output {
+                tokio::runtime::Builder::new()
+                    .basic_scheduler()
+                    .enable_all()
+                    .build()
+                    .expect("failed to build tokio runtime")
+                    .block_on(future)
+            }
+
+            #[test]
+            fn mock_async_results() {
+                let daemon = daemon();
+                daemon.add_device(device());
+
+                block_on(async {
+                    let client = AsyncClient::with_address(daemon.address()).unwrap();
+                    assert_eq!(&*client.devices().await.unwrap()[0].name, "Mock Device");
+
+                    let why = client.results(&device()).await.unwrap_err();
+                    assert_eq!(
+                        why.fwupd_error().map(|e| e.kind),
+                        Some(FwupdErrorKind::NothingToDo)
+                    );
+
+                    daemon.set_results(Device {
+                        update_state: Some(UpdateState::Success),
+                        ..device()
+                    });
+
+                    let results = client.results(&device()).await.unwrap().expect("no results");
+                    assert_eq!(results.update_state, Some(UpdateState::Success));
+                });
+            }
+
+            #[test]
+            fn mock_async_signal_sender() {
+                let daemon = daemon();
+
+                block_on(async {
+                    let client = AsyncClient::with_address(daemon.address()).unwrap();
+                    let mut signals = client.listen_signals().await.unwrap();
+
+                    // Another connection on the bus sends a signal as if it were the daemon.
+                    let impostor = Connection::open_private(daemon.address()).unwrap();
+                    impostor.register().unwrap();
+                    let spoofed = Message::new_signal(DBUS_PATH, DBUS_IFACE, "Changed").unwrap();
+                    impostor.send(spoofed).unwrap();
+
+                    // The bus replies to the call after it has routed the signal.
+                    let get_id = Message::new_method_call(
+                        "org.freedesktop.DBus",
+                        "/org/freedesktop/DBus",
+                        "org.freedesktop.DBus",
+                        "GetId",
+                    )
+                    .unwrap();
+                    impostor.send_with_reply_and_block(get_id, 1000).unwrap();
+
+                    daemon.emit(Signal::StatusChanged(Status::Downloading));
+
+                    match signals.next().await {
+                        Some(Signal::StatusChanged(Status::Downloading)) => (),
+                        other => panic!("expected the signal of the daemon, found {:?}", other),
+                    }
+                });
+            }
+
+            #[test]
+            fn mock_async_timeout() {
+                let daemon = daemon();
+                daemon.delay_method("GetDevices", Duration::from_secs(1));
+
+                block_on(async {
+                    let client = AsyncClient::with_address(daemon.address()).unwrap();
+                    let timeout = Some(Duration::from_millis(100));
+                    match client.with_timeout(timeout).devices().await {
+                        Err(Error::Timeout("GetDevices")) => (),
+                        other => panic!("expected the call to time out, found {:?}", other),
+                    }
+                });
+            }
+        }
+    }
 }
@@ -0,0 +1,674 @@
+//! An in-process mock of the fwupd daemon, for testing code built on `Client`.
+//!
+//! The mock serves `org.freedesktop.fwupd` on either a private bus spawned for it, or an existing
+//! bus such as the session bus. Its devices, releases, remotes, and properties are scriptable, and
+//! it records the install requests and metadata updates that it receives.
+
+use crate::{
+    Client, DBusEntries, Device, DeviceId, Error, FeatureFlags, Release, ReleaseFlags, Remote,
+    RemoteId, Request, SecurityAttr, Signal, Value, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
+    PROPERTIES_IFACE,
+};
+use dbus::{
+    arg::{OwnedFd, RefArg, Variant},
+    ffidisp::{Connection, ConnectionItem, NameFlag, RequestNameReply},
+    strings::ErrorName,
+    Message,
+};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CString,
+    io::{self, BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// An error that may occur when starting the mock daemon.
+#[derive(Debug, Error)]
+pub enum MockError {
+    #[error(display = "failed to connect the mock daemon to the bus")]
+    Connection(#[error(cause, no_from)] dbus::Error),
+    #[error(display = "the private bus did not print its address")]
+    NoAddress,
+    #[error(display = "the name {} is already owned on this bus", _0)]
+    NameTaken(&'static str),
+    #[error(display = "failed to read the address of the private bus")]
+    ReadAddress(#[error(cause, no_from)] io::Error),
+    #[error(display = "failed to request the {} name", _0)]
+    RequestName(&'static str, #[error(cause, no_from)] dbus::Error),
+    #[error(display = "DBUS_SESSION_BUS_ADDRESS is not set")]
+    SessionAddress,
+    #[error(display = "failed to spawn dbus-daemon for the private bus")]
+    Spawn(#[error(cause, no_from)] io::Error),
+}
+
+/// An install request received by the mock daemon.
+#[derive(Clone, Debug)]
+pub struct InstallRequest {
+    pub device_id: DeviceId,
+    pub options:   HashMap<Box<str>, Value>,
+}
+
+/// The scriptable state of the mock daemon.
+struct State {
+    approved:      Vec<Box<str>>,
+    blocked:       Vec<Box<str>>,
+    delays:        HashMap<Box<str>, Duration>,
+    devices:       Vec<Device>,
+    errors:        HashMap<Box<str>, (Box<str>, Box<str>)>,
+    feature_flags: FeatureFlags,
+    installs:      Vec<InstallRequest>,
+    metadata:      Vec<RemoteId>,
+    properties:    HashMap<Box<str>, Value>,
+    releases:      HashMap<DeviceId, Vec<Release>>,
+    remotes:       Vec<Remote>,
+    results:       HashMap<DeviceId, Device>,
+    security:      Vec<SecurityAttr>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let properties = cascade! {
+            HashMap::new();
+            ..insert("BatteryLevel".into(), Value::UInt32(101));
+            ..insert("BatteryThreshold".into(), Value::UInt32(101));
+            ..insert("DaemonVersion".into(), Value::Str("1.4.0".into()));
+            ..insert("HostBkc".into(), Value::Str("".into()));
+            ..insert("HostMachineId".into(), Value::Str("mock-machine-id".into()));
+            ..insert("HostProduct".into(), Value::Str("Mock Host".into()));
+            ..insert("HostSecurityId".into(), Value::Str("HSI:0".into()));
+            ..insert("Interactive".into(), Value::Bool(false));
+            ..insert("OnlyTrusted".into(), Value::Bool(true));
+            ..insert("Percentage".into(), Value::UInt32(0));
+            ..insert("Status".into(), Value::UInt32(1));
+            ..insert("Tainted".into(), Value::Bool(false));
+        };
+
+        State {
+            approved: Vec::new(),
+            blocked: Vec::new(),
+            delays: HashMap::new(),
+            devices: Vec::new(),
+            errors: HashMap::new(),
+            feature_flags: FeatureFlags::default(),
+            installs: Vec::new(),
+            metadata: Vec::new(),
+            properties,
+            releases: HashMap::new(),
+            remotes: Vec::new(),
+            results: HashMap::new(),
+            security: Vec::new(),
+        }
+    }
+}
+
+/// A mock fwupd daemon, which serves requests from a background thread until dropped.
+pub struct MockDaemon {
+    address:  Box<str>,
+    bus:      Option<Child>,
+    outgoing: mpsc::Sender<Message>,
+    running:  Arc<AtomicBool>,
+    state:    Arc<Mutex<State>>,
+    thread:   Option<JoinHandle<()>>,
+}
+
+impl MockDaemon {
+    /// Spawns a private `dbus-daemon`, and serves the mock on it.
+    pub fn private() -> Result<Self, MockError> {
+        let mut bus = Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(MockError::Spawn)?;
+
+        let mut address = String::new();
+        let read = BufReader::new(bus.stdout.as_mut().expect("dbus-daemon without stdout"))
+            .read_line(&mut address);
+
+        let address = match read {
+            Ok(_) if !address.trim().is_empty() => address.trim().to_owned(),
+            Ok(_) => {
+                let _ = bus.kill();
+                return Err(MockError::NoAddress);
+            }
+            Err(why) => {
+                let _ = bus.kill();
+                return Err(MockError::ReadAddress(why));
+            }
+        };
+
+        Self::start(address, Some(bus))
+    }
+
+    /// Serves the mock on the session bus.
+    ///
+    /// Only one mock may be served on a session bus at a time.
+    pub fn session() -> Result<Self, MockError> {
+        let address = env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| MockError::SessionAddress)?;
+        Self::with_address(&address)
+    }
+
+    /// Serves the mock on the bus at the given address.
+    pub fn with_address(address: &str) -> Result<Self, MockError> {
+        Self::start(address.to_owned(), None)
+    }
+
+    /// The address of the bus that the mock is served on.
+    pub fn address(&self) -> &str { &self.address }
+
+    /// Creates a client which is connected to the mock.
+    pub fn client(&self) -> Result<Client, Error> { Client::with_address(&self.address) }
+
+    /// Adds a device, which will be returned by `GetDevices`.
+    pub fn add_device(&self, device: Device) { self.state().devices.push(device); }
+
+    /// Adds a release of a device, which will be returned by `GetReleases`.
+    ///
+    /// Releases flagged as upgrades or downgrades will also be returned by `GetUpgrades` and
+    /// `GetDowngrades`.
+    pub fn add_release(&self, device_id: &DeviceId, release: Release) {
+        self.state().releases.entry(device_id.clone()).or_insert_with(Vec::new).push(release);
+    }
+
+    /// Adds a remote, which will be returned by `GetRemotes`.
+    pub fn add_remote(&self, remote: Remote) { self.state().remotes.push(remote); }
+
+    /// Adds a host security attribute, which will be returned by `GetHostSecurityAttrs`.
+    pub fn add_security_attr(&self, attr: SecurityAttr) { self.state().security.push(attr); }
+
+    /// Delays the reply to every call of the given method.
+    pub fn delay_method(&self, method: &str, delay: Duration) {
+        self.state().delays.insert(method.into(), delay);
+    }
+
+    /// Makes every call of the given method fail with a DBus error.
+    pub fn fail_method(&self, method: &str, error_name: &str, message: &str) {
+        self.state().errors.insert(method.into(), (error_name.into(), message.into()));
+    }
+
+    /// The feature flags most recently declared by a client.
+    pub fn feature_flags(&self) -> FeatureFlags { self.state().feature_flags }
+
+    /// The install requests which have been received.
+    pub fn installs(&self) -> Vec<InstallRequest> { self.state().installs.clone() }
+
+    /// The remotes whose metadata was updated by `UpdateMetadata`, in the order of the calls.
+    pub fn metadata_updates(&self) -> Vec<RemoteId> { self.state().metadata.clone() }
+
+    /// Sets the value of a daemon property, without emitting a signal.
+    pub fn set_property(&self, property: &str, value: Value) {
+        self.state().properties.insert(property.into(), value);
+    }
+
+    /// Sets the device which will be returned by `GetResults` for that device's ID.
+    pub fn set_results(&self, device: Device) {
+        self.state().results.insert(device.device_id.clone(), device);
+    }
+
+    /// Emits a signal to listening clients.
+    ///
+    /// Property changes also update the value of the property served by the mock.
+    pub fn emit(&self, signal: Signal) {
+        let fwupd_signal = |member: &str| {
+            Message::new_signal(DBUS_PATH, DBUS_IFACE, member).expect("invalid signal")
+        };
+
+        let message = match signal {
+            Signal::Changed => fwupd_signal("Changed"),
+            Signal::DeviceAdded(device) => {
+                fwupd_signal("DeviceAdded").append1(device_entries(&device))
+            }
+            Signal::DeviceChanged(device) => {
+                fwupd_signal("DeviceChanged").append1(device_entries(&device))
+            }
+            Signal::DeviceRemoved(device) => {
+                fwupd_signal("DeviceRemoved").append1(device_entries(&device))
+            }
+            Signal::DeviceRequest(request) => {
+                fwupd_signal("DeviceRequest").append1(request_entries(&request))
+            }
+            Signal::DaemonVersionChanged(version) => {
+                self.property_changed("DaemonVersion", Value::Str(version))
+            }
+            Signal::PercentageChanged(percentage) => {
+                self.property_changed("Percentage", Value::UInt32(u32::from(percentage)))
+            }
+            Signal::StatusChanged(status) => {
+                self.property_changed("Status", Value::UInt32(status as u32))
+            }
+            Signal::TaintedChanged(tainted) => {
+                self.property_changed("Tainted", Value::Bool(tainted))
+            }
+            Signal::PropertyChanged(property, value) => self.property_changed(&property, value),
+            Signal::PropertyInvalidated(property) => {
+                Message::new_signal(DBUS_PATH, PROPERTIES_IFACE, "PropertiesChanged")
+                    .expect("invalid signal")
+                    .append3(DBUS_IFACE, DBusEntries::new(), vec![String::from(property)])
+            }
+        };
+
+        let _ = self.outgoing.send(message);
+    }
+
+    fn property_changed(&self, property: &str, value: Value) -> Message {
+        let changed: DBusEntries = cascade! {
+            HashMap::new();
+            ..insert(property.to_owned(), Variant(to_refarg(&value)));
+        };
+
+        self.set_property(property, value);
+
+        Message::new_signal(DBUS_PATH, PROPERTIES_IFACE, "PropertiesChanged")
+            .expect("invalid signal")
+            .append3(DBUS_IFACE, changed, Vec::<String>::new())
+    }
+
+    fn start(address: String, bus: Option<Child>) -> Result<Self, MockError> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let running = Arc::new(AtomicBool::new(true));
+        let (outgoing, receiver) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let thread = {
+            let (address, state, running) = (address.clone(), state.clone(), running.clone());
+            thread::spawn(move || {
+                let connection = match connect(&address) {
+                    Ok(connection) => {
+                        let _ = ready_tx.send(Ok(()));
+                        connection
+                    }
+                    Err(why) => {
+                        let _ = ready_tx.send(Err(why));
+                        return;
+                    }
+                };
+
+                serve(&connection, &state, &receiver, &running);
+            })
+        };
+
+        let daemon = MockDaemon {
+            address: address.into(),
+            bus,
+            outgoing,
+            running,
+            state,
+            thread: Some(thread),
+        };
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(daemon),
+            Ok(Err(why)) => Err(why),
+            Err(_) => panic!("mock daemon thread exited before connecting"),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<State> { self.state.lock().unwrap() }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        if let Some(mut bus) = self.bus.take() {
+            let _ = bus.kill();
+            let _ = bus.wait();
+        }
+    }
+}
+
+fn connect(address: &str) -> Result<Connection, MockError> {
+    let connection = Connection::open_private(address).map_err(MockError::Connection)?;
+    connection.register().map_err(MockError::Connection)?;
+
+    let reply = connection
+        .register_name(DBUS_NAME, NameFlag::DoNotQueue.value())
+        .map_err(|why| MockError::RequestName(DBUS_NAME, why))?;
+
+    match reply {
+        RequestNameReply::PrimaryOwner => (),
+        _ => return Err(MockError::NameTaken(DBUS_NAME)),
+    }
+
+    connection.register_object_path(DBUS_PATH).map_err(MockError::Connection)?;
+    Ok(connection)
+}
+
+fn serve(
+    connection: &Connection,
+    state: &Mutex<State>,
+    outgoing: &mpsc::Receiver<Message>,
+    running: &AtomicBool,
+) {
+    while running.load(Ordering::SeqCst) {
+        for message in outgoing.try_iter() {
+            let _ = connection.send(message);
+        }
+
+        for item in connection.iter(50) {
+            match item {
+                ConnectionItem::MethodCall(message) => {
+                    let delay = message
+                        .member()
+                        .and_then(|member| state.lock().unwrap().delays.get(&*member).cloned());
+
+                    if let Some(delay) = delay {
+                        thread::sleep(delay);
+                    }
+
+                    let _ = connection.send(handle(&mut state.lock().unwrap(), &message));
+                }
+                ConnectionItem::Nothing => break,
+                _ => (),
+            }
+        }
+    }
+}
+
+fn handle(state: &mut State, message: &Message) -> Message {
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+
+    if interface == PROPERTIES_IFACE {
+        return match member.as_str() {
+            "Get" => match message.read2::<&str, &str>() {
+                Ok((_, property)) => match state.properties.get(property) {
+                    Some(value) => message.method_return().append1(Variant(to_refarg(value))),
+                    None => error(message, "org.freedesktop.DBus.Error.UnknownProperty", property),
+                },
+                Err(why) => {
+                    error(message, "org.freedesktop.DBus.Error.InvalidArgs", &why.to_string())
+                }
+            },
+            "GetAll" => {
+                let properties: DBusEntries = state
+                    .properties
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), Variant(to_refarg(value))))
+                    .collect();
+
+                message.method_return().append1(properties)
+            }
+            _ => error(message, "org.freedesktop.DBus.Error.UnknownMethod", &member),
+        };
+    }
+
+    if let Some((name, description)) = state.errors.get(member.as_str()) {
+        return error(message, name, description);
+    }
+
+    let device_id = || message.get1::<&str>().map(|id| DeviceId(id.into())).unwrap_or_default();
+
+    let releases = |state: &State, flags: ReleaseFlags| -> Vec<DBusEntries> {
+        state
+            .releases
+            .get(&device_id())
+            .into_iter()
+            .flat_map(|releases| releases.iter())
+            .filter(|release| release.flags.contains(flags))
+            .map(release_entries)
+            .collect()
+    };
+
+    let checksums = || -> Vec<Box<str>> {
+        message.get1::<Vec<&str>>().unwrap_or_default().into_iter().map(Box::from).collect()
+    };
+
+    let checksums_reply = |list: &[Box<str>]| {
+        message.method_return().append1(list.iter().map(|c| &**c).collect::<Vec<&str>>())
+    };
+
+    match member.as_str() {
+        "GetApprovedFirmware" => checksums_reply(&state.approved),
+        "GetBlockedFirmware" => checksums_reply(&state.blocked),
+        "GetDevices" => message
+            .method_return()
+            .append1(state.devices.iter().map(device_entries).collect::<Vec<_>>()),
+        "GetDowngrades" => {
+            message.method_return().append1(releases(state, ReleaseFlags::IS_DOWNGRADE))
+        }
+        "GetHostSecurityAttrs" => message
+            .method_return()
+            .append1(state.security.iter().map(security_attr_entries).collect::<Vec<_>>()),
+        "GetReleases" => message.method_return().append1(releases(state, ReleaseFlags::empty())),
+        "GetRemotes" => message
+            .method_return()
+            .append1(state.remotes.iter().map(remote_entries).collect::<Vec<_>>()),
+        "GetResults" => match state.results.get(&device_id()) {
+            Some(device) => message.method_return().append1(device_entries(device)),
+            None => error(message, "org.freedesktop.fwupd.NothingToDo", "no results to show"),
+        },
+        "GetUpgrades" => message.method_return().append1(releases(state, ReleaseFlags::IS_UPGRADE)),
+        "Install" => match message.read3::<&str, OwnedFd, DBusEntries>() {
+            Ok((id, _fd, options)) => {
+                state.installs.push(InstallRequest {
+                    device_id: DeviceId(id.into()),
+                    options:   options
+                        .iter()
+                        .map(|(key, value)| (key.as_str().into(), Value::from(&*value.0)))
+                        .collect(),
+                });
+
+                message.method_return()
+            }
+            Err(why) => error(message, "org.freedesktop.DBus.Error.InvalidArgs", &why.to_string()),
+        },
+        "SetApprovedFirmware" => {
+            state.approved = checksums();
+            message.method_return()
+        }
+        "SetBlockedFirmware" => {
+            state.blocked = checksums();
+            message.method_return()
+        }
+        "SetFeatureFlags" => match message.read1::<u64>() {
+            Ok(flags) => {
+                state.feature_flags = FeatureFlags::from_bits_truncate(flags);
+                message.method_return()
+            }
+            Err(why) => error(message, "org.freedesktop.DBus.Error.InvalidArgs", &why.to_string()),
+        },
+        "UpdateMetadata" => match message.read3::<&str, OwnedFd, OwnedFd>() {
+            Ok((id, _data, _signature)) => {
+                state.metadata.push(RemoteId(id.into()));
+                message.method_return()
+            }
+            Err(why) => error(message, "org.freedesktop.DBus.Error.InvalidArgs", &why.to_string()),
+        },
+        "Activate" | "ClearResults" | "ModifyDevice" | "ModifyRemote" | "Unlock" | "Verify"
+        | "VerifyUpdate" => message.method_return(),
+        _ => error(message, "org.freedesktop.DBus.Error.UnknownMethod", &member),
+    }
+}
+
+fn error(message: &Message, name: &str, description: &str) -> Message {
+    let name = ErrorName::new(name.to_owned()).expect("invalid error name");
+    let description = CString::new(description.replace('\0', "")).unwrap();
+    message.error(&name, &description)
+}
+
+/// Converts a value into a form which can be appended to a message.
+fn to_refarg(value: &Value) -> Box<dyn RefArg> {
+    match *value {
+        Value::Bool(value) => Box::new(value),
+        Value::Byte(value) => Box::new(value),
+        Value::Int16(value) => Box::new(value),
+        Value::UInt16(value) => Box::new(value),
+        Value::Int32(value) => Box::new(value),
+        Value::UInt32(value) => Box::new(value),
+        Value::Int64(value) => Box::new(value),
+        Value::UInt64(value) => Box::new(value),
+        Value::Double(value) => Box::new(value),
+        Value::Str(ref value) => Box::new(value.to_string()),
+        Value::Variant(ref value) => Box::new(Variant(to_refarg(value))),
+        Value::Array(ref values) if values.iter().all(|value| value.as_str().is_some()) => {
+            Box::new(
+                values.iter().filter_map(Value::as_str).map(String::from).collect::<Vec<String>>(),
+            )
+        }
+        ref other => {
+            warn!("mock daemon cannot serve {:?}", other);
+            Box::new(String::new())
+        }
+    }
+}
+
+/// Builds the `a{sv}` dictionary which describes an object.
+#[derive(Default)]
+struct Entries(DBusEntries);
+
+impl Entries {
+    fn add<T: RefArg + 'static>(&mut self, key: &str, value: T) -> &mut Self {
+        self.0.insert(key.to_owned(), Variant(Box::new(value)));
+        self
+    }
+
+    fn add_opt<T: RefArg + 'static>(&mut self, key: &str, value: Option<T>) -> &mut Self {
+        if let Some(value) = value {
+            self.add(key, value);
+        }
+
+        self
+    }
+
+    fn add_str(&mut self, key: &str, value: &str) -> &mut Self {
+        self.add(key, value.to_owned())
+    }
+
+    fn add_strs(&mut self, key: &str, values: &[Box<str>]) -> &mut Self {
+        self.add(key, values.iter().map(|value| value.to_string()).collect::<Vec<String>>())
+    }
+
+    fn add_extra(&mut self, extra: &HashMap<Box<str>, Value>) -> DBusEntries {
+        for (key, value) in extra {
+            self.0.insert(key.to_string(), Variant(to_refarg(value)));
+        }
+
+        std::mem::replace(&mut self.0, HashMap::new())
+    }
+}
+
+fn device_entries(device: &Device) -> DBusEntries {
+    let string = |value: &Option<Box<str>>| value.as_ref().map(|value| value.to_string());
+
+    Entries::default()
+        .add_opt("Branch", string(&device.branch))
+        .add_opt("Checksum", string(&device.checksum))
+        .add("Created", device.created)
+        .add_opt("Description", string(&device.description))
+        .add_str("DeviceId", &device.device_id)
+        .add("Flags", device.flags.bits())
+        .add_opt("FlashesLeft", device.flashes_left)
+        .add_strs("Guid", &device.guid)
+        .add_strs("Icon", &device.icon)
+        .add_opt("InstallDuration", device.install_duration)
+        .add_strs("InstanceIds", &device.instance_ids)
+        .add_opt("Modified", device.modified)
+        .add_str("Name", &device.name)
+        .add_opt("ParentDeviceId", device.parent_device_id.as_ref().map(|id| id.to_string()))
+        .add_str("Plugin", &device.plugin)
+        .add_opt("Serial", string(&device.serial))
+        .add_opt("Summary", string(&device.summary))
+        .add_opt("UpdateError", string(&device.update_error))
+        .add_opt("UpdateMessage", string(&device.update_message))
+        .add_opt("UpdateState", device.update_state.map(|state| state as u32))
+        .add_str("Vendor", &device.vendor)
+        .add_str("VendorId", &device.vendor_id)
+        .add_str("Version", &device.version)
+        .add_opt("VersionBootloader", string(&device.version_bootloader))
+        .add_opt("VersionFormat", device.version_format.map(|format| format as u32))
+        .add_opt("VersionLowest", string(&device.version_lowest))
+        .add_extra(&device.extra)
+}
+
+fn release_entries(release: &Release) -> DBusEntries {
+    let string = |value: &Option<Box<str>>| value.as_ref().map(|value| value.to_string());
+
+    Entries::default()
+        .add_str("AppstreamId", &release.appstream_id)
+        .add_opt("Branch", string(&release.branch))
+        .add_strs("Categories", &release.categories)
+        .add("Checksum", release.checksums.join(","))
+        .add("Created", release.created)
+        .add_str("Description", &release.description)
+        .add_opt("DetailsUrl", string(&release.details_url))
+        .add_str("Filename", &release.filename)
+        .add("Flags", release.flags.bits())
+        .add_str("Homepage", &release.homepage)
+        .add("InstallDuration", release.install_duration)
+        .add_str("License", &release.license)
+        .add_strs("Locations", &release.locations)
+        .add_str("Name", &release.name)
+        .add_opt("Protocol", string(&release.protocol))
+        .add_str("RemoteId", &release.remote_id)
+        .add("Size", release.size)
+        .add_opt("SourceUrl", string(&release.source_url))
+        .add_str("Summary", &release.summary)
+        .add("TrustFlags", release.trust_flags.bits())
+        .add_opt("UpdateMessage", string(&release.update_message))
+        .add_str("Uri", &release.uri)
+        .add_str("Vendor", &release.vendor)
+        .add_str("Version", &release.version)
+        .add_extra(&release.extra)
+}
+
+fn remote_entries(remote: &Remote) -> DBusEntries {
+    let string = |value: &Option<Box<str>>| value.as_ref().map(|value| value.to_string());
+
+    Entries::default()
+        .add_opt("Agreement", string(&remote.agreement))
+        .add("ApprovalRequired", remote.approval_required)
+        .add_opt("Checksum", string(&remote.checksum))
+        .add("Enabled", remote.enabled)
+        .add_str("FilenameCache", &remote.filename_cache)
+        .add_str("FilenameSource", &remote.filename_source)
+        .add_opt("FirmwareBaseUri", string(&remote.firmware_base_uri))
+        .add("Keyring", remote.keyring as u32)
+        .add("ModificationTime", remote.modification_time)
+        .add_opt("Password", string(&remote.password))
+        .add("Priority", i32::from(remote.priority))
+        .add_str("RemoteId", &remote.remote_id)
+        .add_opt("ReportUri", string(&remote.report_uri))
+        .add_str("Title", &remote.title)
+        .add("Type", remote.kind as u32)
+        .add_opt("Username", string(&remote.username))
+        .add_opt("Uri", string(&remote.uri))
+        .add_extra(&remote.extra)
+}
+
+fn request_entries(request: &Request) -> DBusEntries {
+    let string = |value: &Option<Box<str>>| value.as_ref().map(|value| value.to_string());
+
+    Entries::default()
+        .add_str("AppstreamId", &request.id)
+        .add("Created", request.created)
+        .add_str("DeviceId", &request.device_id)
+        .add("RequestKind", request.kind as u32)
+        .add_opt("UpdateImage", string(&request.image))
+        .add_opt("UpdateMessage", string(&request.message))
+        .add_extra(&request.extra)
+}
+
+fn security_attr_entries(attr: &SecurityAttr) -> DBusEntries {
+    Entries::default()
+        .add_str("AppstreamId", &attr.appstream_id)
+        .add("Created", attr.created)
+        .add("Flags", attr.flags.bits())
+        .add_strs("Guid", &attr.guids)
+        .add("HsiLevel", attr.hsi_level)
+        .add("HsiResult", attr.result as u32)
+        .add_str("Name", &attr.name)
+        .add_strs("Obsoletes", &attr.obsoletes)
+        .add_str("Plugin", &attr.plugin)
+        .add_opt("Uri", attr.url.as_ref().map(|url| url.to_string()))
+        .add_extra(&attr.extra)
+}
@@ -0,0 +1,688 @@
+use crate::{
+    call_error, common, decode_entries, firmware, install_options, properties, property_error,
+    signal, Bus, ClientBuilder, DBusEntries, DaemonProperties, DecodeError, Device, DeviceId,
+    Error, FeatureFlags, FirmwarePolicy, FlashEvent, HttpTransport, InstallFlags, Release,
+    ReleaseFlags, Remote, RemoteId, SecurityAttr, Signal, Status, DBUS_IFACE, PROPERTIES_IFACE,
+};
+use dbus::{
+    arg::{AppendAll, Arg, Get, Iter, OwnedFd, ReadAll, TypeMismatchError},
+    channel::{Channel, Sender},
+    message::{MatchRule, MessageType},
+    nonblock::{stdintf::org_freedesktop_dbus::Properties, MsgMatch, Proxy, SyncConnection},
+    Message,
+};
+use futures::{
+    future::{self, Future},
+    stream::{self, Stream, StreamExt},
+    task::{Context, Poll},
+};
+use std::{
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    os::unix::io::IntoRawFd,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The nonblock proxy requires a timeout, so this is used when waiting forever.
+const TIMEOUT: Duration = Duration::from_secs(std::u32::MAX as u64);
+
+/// The bus name and interface of the message bus itself.
+const BUS_DAEMON: &str = "org.freedesktop.DBus";
+const BUS_DAEMON_PATH: &str = "/org/freedesktop/DBus";
+
+/// A non-blocking DBus client for interacting with the fwupd daemon.
+///
+/// Offers the same methods as `Client`, as futures which can be awaited from a tokio runtime.
+/// A pending call is cancelled by dropping its future.
+#[derive(Clone)]
+pub struct AsyncClient {
+    connection: Arc<SyncConnection>,
+    bus_name:   Arc<str>,
+    path:       Arc<str>,
+    timeout:    Option<Duration>,
+    user_agent: Arc<Mutex<Option<Box<str>>>>,
+}
+
+impl AsyncClient {
+    /// Connects to the daemon on the system bus.
+    ///
+    /// Must be called from within a tokio runtime, which will drive the connection.
+    pub fn new() -> Result<Self, Error> { ClientBuilder::new().build_async() }
+
+    /// Connects to a daemon on the session bus.
+    ///
+    /// Must be called from within a tokio runtime, which will drive the connection.
+    pub fn session() -> Result<Self, Error> {
+        ClientBuilder::new().bus(Bus::Session).build_async()
+    }
+
+    /// Connects to a daemon on the bus at the given address.
+    ///
+    /// Must be called from within a tokio runtime, which will drive the connection.
+    pub fn with_address(address: &str) -> Result<Self, Error> {
+        ClientBuilder::new().bus(Bus::Address(address.into())).build_async()
+    }
+
+    /// How long method calls wait for a reply before failing with `Error::Timeout`.
+    pub fn timeout(&self) -> Option<Duration> { self.timeout }
+
+    /// Creates a client sharing this connection, whose method calls use a different timeout.
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self.clone() }
+    }
+
+    pub(crate) fn from_builder(builder: ClientBuilder) -> Result<Self, Error> {
+        builder.validate()?;
+        let ClientBuilder { bus, bus_name, feature_flags, path, timeout } = builder;
+
+        let (resource, connection) = match bus {
+            Bus::System => dbus_tokio::connection::new_system_sync(),
+            Bus::Session => dbus_tokio::connection::new_session_sync(),
+            Bus::Address(ref address) => Channel::open_private(address).and_then(|mut channel| {
+                channel.register()?;
+                dbus_tokio::connection::from_channel(channel)
+            }),
+        }
+        .map_err(Error::Connection)?;
+
+        tokio::spawn(async move {
+            let why = resource.await;
+            error!("lost connection to the {:?} bus: {}", bus, why);
+        });
+
+        let client = Self {
+            connection,
+            bus_name: bus_name.into(),
+            path: path.into(),
+            timeout,
+            user_agent: Arc::new(Mutex::new(None)),
+        };
+
+        // Queued before any other call can be made, without waiting for the reply.
+        if let Some(flags) = feature_flags {
+            const METHOD: &str = "SetFeatureFlags";
+            let message =
+                Message::new_method_call(&*client.bus_name, &*client.path, DBUS_IFACE, METHOD)
+                    .map_err(|why| Error::NewMethodCall(METHOD, why))?
+                    .append1(flags.bits());
+
+            client.connection.send(message).map_err(|()| Error::Send(METHOD))?;
+        }
+
+        Ok(client)
+    }
+
+    /// Activate a firmware update on the device.
+    pub async fn activate<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Activate", id.as_ref().as_ref()).await
+    }
+
+    /// The checksums of firmware which have been approved for installation.
+    pub async fn approved_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.get_checksums("GetApprovedFirmware").await
+    }
+
+    /// The system battery level, as a percentage, if it is known.
+    pub async fn battery_level(&self) -> Result<Option<u8>, Error> {
+        self.get_property::<u32>("BatteryLevel").await.map(properties::battery_level)
+    }
+
+    /// The battery level required for updates, as a percentage, if it is known.
+    pub async fn battery_threshold(&self) -> Result<Option<u8>, Error> {
+        self.get_property::<u32>("BatteryThreshold").await.map(properties::battery_level)
+    }
+
+    /// The checksums of firmware which may never be installed.
+    pub async fn blocked_firmware(&self) -> Result<Vec<Box<str>>, Error> {
+        self.get_checksums("GetBlockedFirmware").await
+    }
+
+    /// The releases of a device which the approved and blocked firmware lists would block, and
+    /// the flags that explain why.
+    pub async fn blocked_releases<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+    ) -> Result<Vec<(Release, ReleaseFlags)>, Error> {
+        let policy = self.firmware_policy().await?;
+        let releases = self.releases(device_id).await?;
+        Ok(policy.blocked(&releases).into_iter().map(|(r, flags)| (r.clone(), flags)).collect())
+    }
+
+    /// The releases of a device which belong to a different branch than its installed firmware.
+    ///
+    /// Installing one of these releases with `switch_branch()` moves the device to its branch.
+    pub async fn branch_releases(&self, device: &Device) -> Result<Vec<Release>, Error> {
+        let releases = self.releases(device).await?;
+        Ok(releases.into_iter().filter(|release| release.branch != device.branch).collect())
+    }
+
+    /// Clears the results of an offline update.
+    pub async fn clear_results<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("ClearResults", id.as_ref().as_ref()).await
+    }
+
+    /// Gets every property of the daemon in a single call.
+    pub async fn daemon_properties(&self) -> Result<DaemonProperties, Error> {
+        const METHOD: &str = "GetAll";
+
+        let properties = self
+            .timed(METHOD, async {
+                self.proxy().get_all(DBUS_IFACE).await.map_err(|why| call_error(METHOD, why))
+            })
+            .await?;
+
+        DaemonProperties::try_from(properties).map_err(|why| Error::Decode(METHOD, why))
+    }
+
+    /// The version of this daemon.
+    pub async fn daemon_version(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("DaemonVersion").await.map(Box::from)
+    }
+
+    /// Gets details about a local firmware file.
+    pub async fn details<H: IntoRawFd>(&self, handle: H) -> Result<Vec<DBusEntries>, Error> {
+        self.get_handle_method("GetDetails", handle).await
+    }
+
+    /// Gets a list of all the devices that are supported.
+    pub async fn devices(&self) -> Result<Vec<Device>, Error> {
+        self.get_method("GetDevices", ()).await
+    }
+
+    /// Get a list of all the downgrades possible for a specific device.
+    pub async fn downgrades<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+    ) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetDowngrades", (device_id,)).await
+    }
+
+    /// Fetches firmware from a remote and caches it for later use.
+    ///
+    /// The download is performed on tokio's blocking thread pool, so the callback must be `Send`.
+    pub async fn fetch_firmware_from_release<H, C>(
+        &self,
+        client: &H,
+        device: &Device,
+        release: &Release,
+        callback: Option<C>,
+    ) -> Result<(PathBuf, Option<File>), Error>
+    where
+        H: HttpTransport + Clone + Send + 'static,
+        C: FnMut(FlashEvent) + Send + 'static,
+    {
+        let remote = self.remote(release).await?;
+        let user_agent = self.user_agent().await?;
+        let (client, device, release) = (client.clone(), device.clone(), release.clone());
+
+        tokio::task::spawn_blocking(move || {
+            let cache_dir = common::cache_dir();
+            firmware::fetch(&client, &user_agent, &cache_dir, &remote, &device, &release, callback)
+        })
+        .await
+        .map_err(|why| Error::BlockingTask("fetch_firmware_from_release", Box::new(why)))?
+    }
+
+    /// Gets both the approved and blocked firmware lists.
+    pub async fn firmware_policy(&self) -> Result<FirmwarePolicy, Error> {
+        let approved = self.approved_firmware().await?;
+        let blocked = self.blocked_firmware().await?;
+        Ok(FirmwarePolicy { approved, blocked })
+    }
+
+    /// Gets a list of all the past firmware updates.
+    pub async fn history<H: IntoRawFd>(&self, handle: H) -> Result<Vec<Device>, Error> {
+        decode_entries("GetHistory", self.get_handle_method("GetHistory", handle).await?)
+    }
+
+    /// The best known configuration of the host, if one is set.
+    pub async fn host_bkc(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostBkc").await.map(Box::from)
+    }
+
+    /// The machine ID of the host.
+    pub async fn host_machine_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostMachineId").await.map(Box::from)
+    }
+
+    /// The product name of the host.
+    pub async fn host_product(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostProduct").await.map(Box::from)
+    }
+
+    /// Gets the host security attributes, which were tested by the plugins of the daemon.
+    pub async fn host_security_attrs(&self) -> Result<Vec<SecurityAttr>, Error> {
+        self.get_method("GetHostSecurityAttrs", ()).await
+    }
+
+    /// The host security ID that was calculated by the daemon, such as `HSI:2!`.
+    pub async fn host_security_id(&self) -> Result<Box<str>, Error> {
+        self.get_property::<String>("HostSecurityId").await.map(Box::from)
+    }
+
+    /// Schedules a firmware to be installed.
+    pub async fn install<D: AsRef<DeviceId>, H: IntoRawFd>(
+        &self,
+        id: D,
+        reason: &str,
+        filename: &Path,
+        handle: Option<H>,
+        flags: InstallFlags,
+    ) -> Result<(), Error> {
+        let fd = match handle {
+            Some(handle) => handle.into_raw_fd(),
+            None => OpenOptions::new()
+                .read(true)
+                .open(filename)
+                .map_err(Error::FirmwareOpen)?
+                .into_raw_fd(),
+        };
+
+        let options = install_options(reason, filename, flags);
+        let id: &str = id.as_ref().as_ref();
+
+        self.call_method("Install", (id, unsafe { OwnedFd::new(fd) }, options)).await
+    }
+
+    /// If the daemon is able to interact with the user, such as to show a prompt.
+    pub async fn interactive(&self) -> Result<bool, Error> {
+        self.get_property::<bool>("Interactive").await
+    }
+
+    /// Listens for signals from the DBus daemon.
+    ///
+    /// Signals will be received for as long as the returned stream is alive. Signals which were
+    /// not sent by the current owner of the daemon's bus name are discarded.
+    pub async fn listen_signals(&self) -> Result<SignalStream, Error> {
+        let fwupd_rule = MatchRule::new()
+            .with_type(MessageType::Signal)
+            .with_interface(DBUS_IFACE)
+            .with_path(self.path.to_string());
+
+        let properties_rule = MatchRule::new_signal(PROPERTIES_IFACE, "PropertiesChanged")
+            .with_path(self.path.to_string());
+
+        let owner_rule =
+            MatchRule::new_signal(BUS_DAEMON, "NameOwnerChanged").with_sender(BUS_DAEMON);
+
+        // Unlike the blocking client, the rules can not match the daemon's bus name, because the
+        // connection compares it against the unique name of each message's sender when
+        // dispatching. Signals are instead filtered by the unique name of the bus name's owner,
+        // which is looked up after its changes are matched, so that none are missed.
+        let (owner_match, owner_stream) =
+            self.connection.add_match(owner_rule).await.map_err(Error::AddMatch)?.msg_stream();
+
+        let owner = Arc::new(Mutex::new(self.name_owner().await?));
+
+        let (fwupd_match, fwupd_stream) =
+            self.connection.add_match(fwupd_rule).await.map_err(Error::AddMatch)?.msg_stream();
+
+        let (properties_match, properties_stream) = self
+            .connection
+            .add_match(properties_rule)
+            .await
+            .map_err(Error::AddMatch)?
+            .msg_stream();
+
+        let owner_changes = {
+            let (bus_name, owner) = (self.bus_name.clone(), owner.clone());
+            owner_stream.filter_map(move |message| {
+                if let Ok((name, _, new_owner)) = message.read3::<&str, &str, &str>() {
+                    if name == &*bus_name {
+                        let new_owner = Some(new_owner).filter(|new| !new.is_empty());
+                        *owner.lock().unwrap() = new_owner.map(String::from);
+                    }
+                }
+
+                future::ready(None::<Message>)
+            })
+        };
+
+        let signals = stream::select(owner_changes, stream::select(fwupd_stream, properties_stream))
+            .filter(move |message: &Message| {
+                let owner = owner.lock().unwrap();
+                let from_owner = match (message.sender(), &*owner) {
+                    (Some(sender), Some(owner)) => &*sender == owner.as_str(),
+                    _ => false,
+                };
+
+                future::ready(from_owner)
+            })
+            .flat_map(|message| stream::iter(signal::parse(message)));
+
+        Ok(SignalStream {
+            inner:    Box::pin(signals),
+            _matches: (owner_match, fwupd_match, properties_match),
+        })
+    }
+
+    /// Modifies a device in some way.
+    pub async fn modify_device<D: AsRef<DeviceId>>(
+        &self,
+        device_id: D,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.call_method("ModifyDevice", (device_id, key, value)).await
+    }
+
+    /// Modifies a remote in some way.
+    pub async fn modify_remote<R: AsRef<RemoteId>>(
+        &self,
+        remote_id: R,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let remote_id: &str = remote_id.as_ref().as_ref();
+        self.call_method("ModifyRemote", (remote_id, key, value)).await
+    }
+
+    /// If the daemon only allows firmware with trusted payloads to be installed.
+    pub async fn only_trusted(&self) -> Result<bool, Error> {
+        self.get_property::<bool>("OnlyTrusted").await
+    }
+
+    /// The job percentage completion, or 0 for unknown.
+    pub async fn percentage(&self) -> Result<u8, Error> {
+        self.get_property::<u32>("Percentage").await.map(|v| v as u8)
+    }
+
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.proxy()
+            .method_call("org.freedesktop.DBus.Peer", "Ping", ())
+            .await
+            .map_err(Error::Ping)
+    }
+
+    /// Gets a list of all the releases for a specific device.
+    pub async fn releases<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetReleases", (device_id,)).await
+    }
+
+    /// Find the remote with the given ID.
+    pub async fn remote<D: AsRef<RemoteId>>(&self, id: D) -> Result<Remote, Error> {
+        self.remotes()
+            .await?
+            .into_iter()
+            .find(|remote| &remote.remote_id == id.as_ref())
+            .ok_or(Error::RemoteNotFound)
+    }
+
+    /// Gets the list of remotes.
+    pub async fn remotes(&self) -> Result<Vec<Remote>, Error> {
+        self.get_method("GetRemotes", ()).await
+    }
+
+    /// Gets the results of an offline update.
+    pub async fn results<D: AsRef<DeviceId>>(&self, id: D) -> Result<Option<Device>, Error> {
+        let id: &str = id.as_ref().as_ref();
+        let OptionalEntries(entries) = self.call_method("GetResults", (id,)).await?;
+        entries.map(Device::try_from).transpose().map_err(|why| Error::Decode("GetResults", why))
+    }
+
+    /// Replaces the checksums of firmware which have been approved for installation.
+    pub async fn set_approved_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        self.set_checksums("SetApprovedFirmware", checksums).await
+    }
+
+    /// Replaces the checksums of firmware which may never be installed.
+    pub async fn set_blocked_firmware<S: AsRef<str>>(&self, checksums: &[S]) -> Result<(), Error> {
+        self.set_checksums("SetBlockedFirmware", checksums).await
+    }
+
+    /// Tells the daemon which features this client supports.
+    ///
+    /// The daemon only sends requests, and only allows actions such as switching branches, to
+    /// clients which have declared support for them.
+    pub async fn set_feature_flags(&self, flags: FeatureFlags) -> Result<(), Error> {
+        self.call_method("SetFeatureFlags", (flags.bits(),)).await
+    }
+
+    /// The daemon status, e.g. `Decompressing`.
+    pub async fn status(&self) -> Result<Status, Error> {
+        self.get_property::<u32>("Status").await.map(|v| Status::from(v as u8))
+    }
+
+    /// Installs a release from a different branch than the firmware installed on the device.
+    ///
+    /// The daemon only allows switching branches for clients which have declared
+    /// `FeatureFlags::SWITCH_BRANCH`. A release which is older than the installed firmware is
+    /// refused, unless `flags` also contains `InstallFlags::ALLOW_OLDER`.
+    pub async fn switch_branch<H, F>(
+        &self,
+        client: &H,
+        device: &Device,
+        release: &Release,
+        flags: InstallFlags,
+        callback: Option<F>,
+    ) -> Result<(), Error>
+    where
+        H: HttpTransport + Clone + Send + 'static,
+        F: FnMut(FlashEvent) + Send + 'static,
+    {
+        let flags = flags | InstallFlags::ALLOW_BRANCH_SWITCH;
+        self.update_device_with_release(client, device, release, flags, callback).await
+    }
+
+    /// If the daemon has been tainted with a third party plugin.
+    pub async fn tainted(&self) -> Result<bool, Error> {
+        self.get_property::<bool>("Tainted").await
+    }
+
+    /// Unlock the device to allow firmware access.
+    pub async fn unlock<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Unlock", id.as_ref().as_ref()).await
+    }
+
+    /// Update firmware for a `Device` with the firmware specified in a `Release`.
+    pub async fn update_device_with_release<H, F>(
+        &self,
+        client: &H,
+        device: &Device,
+        release: &Release,
+        mut flags: InstallFlags,
+        callback: Option<F>,
+    ) -> Result<(), Error>
+    where
+        H: HttpTransport + Clone + Send + 'static,
+        F: FnMut(FlashEvent) + Send + 'static,
+    {
+        if device.only_offline() {
+            flags |= InstallFlags::OFFLINE;
+        }
+
+        // The callback is moved to the blocking pool for the download, and returned afterwards.
+        let callback = callback.map(|cb| Arc::new(Mutex::new(cb)));
+        let download_cb =
+            callback.clone().map(|cb| move |event| (&mut *cb.lock().unwrap())(event));
+
+        let (filename, file) =
+            self.fetch_firmware_from_release(client, device, release, download_cb).await?;
+
+        if let Some(cb) = callback {
+            (&mut *cb.lock().unwrap())(FlashEvent::FlashInProgress);
+        }
+
+        info!("installing firmware for {} ({})", device.name, release.version);
+        self.install(device, "(user)", &filename, file, flags).await
+    }
+
+    /// Adds AppStream resource information from a session client.
+    pub async fn update_metadata<D: IntoRawFd, S: IntoRawFd, R: AsRef<RemoteId>>(
+        &self,
+        remote_id: R,
+        data: D,
+        signature: S,
+    ) -> Result<(), Error> {
+        let remote_id: &str = remote_id.as_ref().as_ref();
+        let args = (remote_id, unsafe { OwnedFd::new(data.into_raw_fd()) }, unsafe {
+            OwnedFd::new(signature.into_raw_fd())
+        });
+
+        self.call_method("UpdateMetadata", args).await
+    }
+
+    /// Get a list of all the upgrades possible for a specific device.
+    pub async fn upgrades<D: AsRef<DeviceId>>(&self, device_id: D) -> Result<Vec<Release>, Error> {
+        let device_id: &str = device_id.as_ref().as_ref();
+        self.get_method("GetUpgrades", (device_id,)).await
+    }
+
+    /// Verifies firmware on a device by reading it back and performing
+    /// a cryptographic hash, typically SHA1.
+    pub async fn verify<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("Verify", id.as_ref().as_ref()).await
+    }
+
+    /// Updates the cryptographic hash stored for a device.
+    pub async fn verify_update<D: AsRef<DeviceId>>(&self, id: D) -> Result<(), Error> {
+        self.action_method("VerifyUpdate", id.as_ref().as_ref()).await
+    }
+
+    async fn action_method(&self, method: &'static str, id: &str) -> Result<(), Error> {
+        self.call_method(method, (id,)).await
+    }
+
+    async fn get_checksums(&self, method: &'static str) -> Result<Vec<Box<str>>, Error> {
+        let (checksums,): (Vec<String>,) = self.call_method(method, ()).await?;
+        Ok(checksums.into_iter().map(Box::from).collect())
+    }
+
+    async fn set_checksums<S: AsRef<str>>(
+        &self,
+        method: &'static str,
+        checksums: &[S],
+    ) -> Result<(), Error> {
+        let checksums: Vec<&str> = checksums.iter().map(AsRef::as_ref).collect();
+        self.call_method(method, (checksums,)).await
+    }
+
+    async fn get_method<T: TryFrom<DBusEntries, Error = DecodeError>, A: AppendAll>(
+        &self,
+        method: &'static str,
+        args: A,
+    ) -> Result<Vec<T>, Error> {
+        let (entries,): (Vec<DBusEntries>,) = self.call_method(method, args).await?;
+        decode_entries(method, entries)
+    }
+
+    async fn get_handle_method<H: IntoRawFd>(
+        &self,
+        method: &'static str,
+        handle: H,
+    ) -> Result<Vec<DBusEntries>, Error> {
+        let (entries,): (Vec<DBusEntries>,) = self
+            .call_method(method, (unsafe { OwnedFd::new(handle.into_raw_fd()) },))
+            .await?;
+
+        Ok(entries)
+    }
+
+    async fn get_property<T: for<'a> Get<'a> + Arg + 'static>(
+        &self,
+        property: &'static str,
+    ) -> Result<T, Error> {
+        self.timed(property, async {
+            self.proxy()
+                .get::<T>(DBUS_IFACE, property)
+                .await
+                .map_err(|why| property_error(property, why))
+        })
+        .await
+    }
+
+    async fn call_method<R: ReadAll + 'static, A: AppendAll>(
+        &self,
+        method: &'static str,
+        args: A,
+    ) -> Result<R, Error> {
+        self.timed(method, async {
+            self.proxy()
+                .method_call(DBUS_IFACE, method, args)
+                .await
+                .map_err(|why| call_error(method, why))
+        })
+        .await
+    }
+
+    /// Waits for the reply of a call, which fails with `Error::Timeout` once the timeout has
+    /// elapsed, as the proxy does not enforce the timeout that it is given.
+    async fn timed<T, F: Future<Output = Result<T, Error>>>(
+        &self,
+        name: &'static str,
+        call: F,
+    ) -> Result<T, Error> {
+        match self.timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, call).await.map_err(|_| Error::Timeout(name))?
+            }
+            None => call.await,
+        }
+    }
+
+    /// The unique name of the connection which owns the daemon's bus name, if it is owned.
+    async fn name_owner(&self) -> Result<Option<String>, Error> {
+        const METHOD: &str = "GetNameOwner";
+
+        let timeout = self.timeout.unwrap_or(TIMEOUT);
+        let proxy = Proxy::new(BUS_DAEMON, BUS_DAEMON_PATH, timeout, &*self.connection);
+
+        self.timed(METHOD, async {
+            let reply: Result<(String,), dbus::Error> =
+                proxy.method_call(BUS_DAEMON, METHOD, (&*self.bus_name,)).await;
+
+            match reply {
+                Ok((owner,)) => Ok(Some(owner)),
+                Err(ref why) if why.name() == Some("org.freedesktop.DBus.Error.NameHasNoOwner") => {
+                    Ok(None)
+                }
+                Err(why) => Err(call_error(METHOD, why)),
+            }
+        })
+        .await
+    }
+
+    fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        let timeout = self.timeout.unwrap_or(TIMEOUT);
+        Proxy::new(&*self.bus_name, &*self.path, timeout, &*self.connection)
+    }
+
+    /// Fetch and cache the user agent.
+    async fn user_agent(&self) -> Result<Box<str>, Error> {
+        if let Some(ref agent) = *self.user_agent.lock().unwrap() {
+            return Ok(agent.clone());
+        }
+
+        let agent: Box<str> = ["fwupd/", &*self.daemon_version().await?].concat().into();
+        *self.user_agent.lock().unwrap() = Some(agent.clone());
+        Ok(agent)
+    }
+}
+
+/// Reads the first argument of a reply if it is a dictionary, as `Message::get1()` does for the
+/// blocking client, so that a reply without results is not treated as an error.
+struct OptionalEntries(Option<DBusEntries>);
+
+impl ReadAll for OptionalEntries {
+    fn read(iter: &mut Iter) -> Result<Self, TypeMismatchError> { Ok(OptionalEntries(iter.get())) }
+}
+
+/// A stream of signals received from the daemon, created by `AsyncClient::listen_signals()`.
+pub struct SignalStream {
+    inner:    Pin<Box<dyn Stream<Item = Signal> + Send>>,
+    _matches: (MsgMatch, MsgMatch, MsgMatch),
+}
+
+impl Stream for SignalStream {
+    type Item = Signal;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Signal>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
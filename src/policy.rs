@@ -0,0 +1,46 @@
+use crate::{Release, ReleaseFlags};
+
+/// The checksums of firmware which the daemon has approved or blocked.
+///
+/// When the approved list is not empty, only releases with an approved checksum may be
+/// installed. Releases with a blocked checksum may never be installed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FirmwarePolicy {
+    pub approved: Vec<Box<str>>,
+    pub blocked:  Vec<Box<str>>,
+}
+
+impl FirmwarePolicy {
+    /// The flags that the daemon would set on the release when applying this policy.
+    ///
+    /// Either `BLOCKED_APPROVAL` or `BLOCKED_VERSION`, or both, if the release is blocked.
+    pub fn flags(&self, release: &Release) -> ReleaseFlags {
+        let contains = |list: &[Box<str>]| {
+            release.checksums.iter().any(|checksum| list.iter().any(|c| c == checksum))
+        };
+
+        let mut flags = ReleaseFlags::empty();
+
+        if !self.approved.is_empty() && !contains(&self.approved) {
+            flags |= ReleaseFlags::BLOCKED_APPROVAL;
+        }
+
+        if contains(&self.blocked) {
+            flags |= ReleaseFlags::BLOCKED_VERSION;
+        }
+
+        flags
+    }
+
+    /// If the release would be blocked by either list.
+    pub fn is_blocked(&self, release: &Release) -> bool { !self.flags(release).is_empty() }
+
+    /// Pairs each release which would be blocked with the flags that explain why.
+    pub fn blocked<'a>(&self, releases: &'a [Release]) -> Vec<(&'a Release, ReleaseFlags)> {
+        releases
+            .iter()
+            .map(|release| (release, self.flags(release)))
+            .filter(|(_, flags)| !flags.is_empty())
+            .collect()
+    }
+}
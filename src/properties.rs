@@ -0,0 +1,77 @@
+use crate::{dbus_helpers::*, DBusEntries, Status, Value};
+use dbus::arg::RefArg;
+use std::{collections::HashMap, convert::TryFrom};
+
+/// The battery level which the daemon reports when the level is unknown.
+const BATTERY_LEVEL_INVALID: u32 = 101;
+
+/// Converts a battery level reported by the daemon, which may be unknown.
+pub(crate) fn battery_level(level: u32) -> Option<u8> {
+    if level >= BATTERY_LEVEL_INVALID {
+        None
+    } else {
+        Some(level as u8)
+    }
+}
+
+/// A snapshot of every property of the daemon, fetched by `Client::daemon_properties()`.
+///
+/// Properties which are not published by older daemons are left at their default values.
+#[derive(Clone, Debug, Default)]
+pub struct DaemonProperties {
+    /// The system battery level, as a percentage, if it is known.
+    pub battery_level:     Option<u8>,
+    /// The battery level required for updates, as a percentage, if it is known.
+    pub battery_threshold: Option<u8>,
+    pub daemon_version:    Box<str>,
+    /// Properties from the daemon which are not recognized by this crate.
+    pub extra:             HashMap<Box<str>, Value>,
+    /// The best known configuration of the host, if one is set.
+    pub host_bkc:          Box<str>,
+    pub host_machine_id:   Box<str>,
+    pub host_product:      Box<str>,
+    pub host_security_id:  Box<str>,
+    /// If the daemon is able to interact with the user, such as to show a prompt.
+    pub interactive:       bool,
+    /// If the daemon only allows firmware with trusted payloads to be installed.
+    pub only_trusted:      bool,
+    pub percentage:        u8,
+    pub status:            Status,
+    pub tainted:           bool,
+}
+
+impl TryFrom<DBusEntries> for DaemonProperties {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
+        let mut properties = DaemonProperties::default();
+
+        for (key, value) in entries {
+            let key = key.as_str();
+            match key {
+                "BatteryLevel" => {
+                    properties.battery_level = battery_level(dbus_u64(&value, key)? as u32)
+                }
+                "BatteryThreshold" => {
+                    properties.battery_threshold = battery_level(dbus_u64(&value, key)? as u32)
+                }
+                "DaemonVersion" => properties.daemon_version = dbus_str(&value, key)?.into(),
+                "HostBkc" => properties.host_bkc = dbus_str(&value, key)?.into(),
+                "HostMachineId" => properties.host_machine_id = dbus_str(&value, key)?.into(),
+                "HostProduct" => properties.host_product = dbus_str(&value, key)?.into(),
+                "HostSecurityId" => properties.host_security_id = dbus_str(&value, key)?.into(),
+                "Interactive" => properties.interactive = dbus_u64(&value, key)? != 0,
+                "OnlyTrusted" => properties.only_trusted = dbus_u64(&value, key)? != 0,
+                "Percentage" => properties.percentage = dbus_u64(&value, key)? as u8,
+                "Status" => properties.status = Status::from(dbus_u64(&value, key)? as u8),
+                "Tainted" => properties.tainted = dbus_u64(&value, key)? != 0,
+                other => {
+                    debug!("unknown property: {} ({}): {:?}", other, value.signature(), value);
+                    properties.extra.insert(other.into(), Value::from(&*value.0));
+                }
+            }
+        }
+
+        Ok(properties)
+    }
+}
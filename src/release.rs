@@ -1,6 +1,6 @@
-use crate::{common::*, dbus_helpers::*, DBusEntry, RemoteId};
+use crate::{common::*, dbus_helpers::*, version, DBusEntries, RemoteId, Value, Version};
 use dbus::arg::RefArg;
-use std::{cmp::Ordering, iter::FromIterator};
+use std::{cmp::Ordering, collections::HashMap, convert::TryFrom};
 
 bitflags! {
     /// Describes attributes of a release.
@@ -32,19 +32,27 @@ impl Default for TrustFlags {
 }
 
 /// Information about an available fwupd remote.
-#[derive(Clone, Debug, Default, Eq)]
+#[derive(Clone, Debug, Default)]
 pub struct Release {
     pub appstream_id:     Box<str>,
+    /// The branch of the firmware, such as `coreboot`, or `None` for the default branch.
+    pub branch:           Option<Box<str>>,
     pub categories:       Box<[Box<str>]>,
     pub checksums:        Box<[Box<str>]>,
     pub created:          u64,
     pub description:      Box<str>,
     pub details_url:      Option<Box<str>>,
+    /// Keys from the daemon which are not recognized by this crate.
+    pub extra:            HashMap<Box<str>, Value>,
     pub filename:         Box<str>,
     pub flags:            ReleaseFlags,
     pub homepage:         Box<str>,
     pub install_duration: u32,
     pub license:          Box<str>,
+    /// The URIs of every mirror of the firmware, in order of preference.
+    ///
+    /// Older daemons only publish a single `uri`.
+    pub locations:        Box<[Box<str>]>,
     pub name:             Box<str>,
     pub protocol:         Option<Box<str>>,
     pub remote_id:        RemoteId,
@@ -58,8 +66,26 @@ pub struct Release {
     pub version:          Box<str>,
 }
 
+impl Release {
+    /// The version of this release, which is compared as libfwupd does.
+    pub fn parsed_version(&self) -> Version { Version::from(self.version.as_ref()) }
+
+    /// The URIs that the firmware may be downloaded from, in the order they should be tried.
+    pub fn locations(&self) -> Vec<&str> {
+        if !self.locations.is_empty() {
+            self.locations.iter().map(AsRef::as_ref).collect()
+        } else if !self.uri.is_empty() {
+            vec![&*self.uri]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
 impl Ord for Release {
-    fn cmp(&self, other: &Self) -> Ordering { self.version.cmp(&other.version) }
+    fn cmp(&self, other: &Self) -> Ordering {
+        version::compare(&self.version, &other.version, version::DEFAULT_FORMAT)
+    }
 }
 
 impl PartialOrd for Release {
@@ -67,71 +93,72 @@ impl PartialOrd for Release {
 }
 
 impl PartialEq for Release {
-    fn eq(&self, other: &Self) -> bool { self.version == other.version }
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
 }
 
+impl Eq for Release {}
+
 impl AsRef<RemoteId> for Release {
     fn as_ref(&self) -> &RemoteId { &self.remote_id }
 }
 
-impl FromIterator<DBusEntry> for Release {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusEntries> for Release {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
         let mut release = Release::default();
 
-        for (key, value) in iter {
+        for (key, value) in entries {
             let key = key.as_str();
             match key {
-                KEY_APPSTREAM_ID => release.appstream_id = dbus_str(&value, key).into(),
-                KEY_CATEGORIES => {
-                    release.categories = value
-                        .as_iter()
-                        .expect("Categories is not a variant")
-                        .flat_map(|array| array.as_iter().expect("Categories is not an iterator"))
-                        .map(|value| dbus_str(&value, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
-                }
+                KEY_APPSTREAM_ID => release.appstream_id = dbus_str(&value, key)?.into(),
+                KEY_BRANCH => release.branch = Some(dbus_str(&value, key)?.into()),
+                KEY_CATEGORIES => release.categories = dbus_str_array(&value, key)?,
                 KEY_CHECKSUM => {
-                    release.checksums = value
-                        .as_iter()
-                        .expect("Checksums is not a variant")
-                        // .flat_map(|array| array.as_iter().expect("Checksums is not an iterator"))
-                        .map(|value| dbus_str(&value, key).into())
-                        .collect::<Vec<Box<str>>>()
-                        .into_boxed_slice()
+                    // The daemon joins the checksums of a release with commas.
+                    release.checksums = match dbus_str(&value, key) {
+                        Ok(checksums) => checksums
+                            .split(',')
+                            .filter(|checksum| !checksum.is_empty())
+                            .map(Box::from)
+                            .collect::<Vec<Box<str>>>()
+                            .into_boxed_slice(),
+                        Err(_) => dbus_str_array(&value, key)?,
+                    }
                 }
-                KEY_DESCRIPTION => release.description = dbus_str(&value, key).into(),
-                KEY_DETAILS_URL => release.details_url = Some(dbus_str(&value, key).into()),
-                KEY_FILENAME => release.filename = dbus_str(&value, key).into(),
+                KEY_DESCRIPTION => release.description = dbus_str(&value, key)?.into(),
+                KEY_DETAILS_URL => release.details_url = Some(dbus_str(&value, key)?.into()),
+                KEY_FILENAME => release.filename = dbus_str(&value, key)?.into(),
                 KEY_FLAGS => {
-                    release.flags = ReleaseFlags::from_bits_truncate(dbus_u64(&value, key))
+                    release.flags = ReleaseFlags::from_bits_truncate(dbus_u64(&value, key)?)
                 }
-                KEY_HOMEPAGE => release.homepage = dbus_str(&value, key).into(),
-                KEY_INSTALL_DURATION => release.install_duration = dbus_u64(&value, key) as u32,
-                KEY_LICENSE => release.license = dbus_str(&value, key).into(),
+                KEY_HOMEPAGE => release.homepage = dbus_str(&value, key)?.into(),
+                KEY_INSTALL_DURATION => release.install_duration = dbus_u64(&value, key)? as u32,
+                KEY_LICENSE => release.license = dbus_str(&value, key)?.into(),
+                KEY_LOCATIONS => release.locations = dbus_str_array(&value, key)?,
                 // KEY_METADATA => (),
-                KEY_NAME => release.name = dbus_str(&value, key).into(),
-                KEY_PROTOCOL => release.protocol = Some(dbus_str(&value, key).into()),
-                KEY_REMOTE_ID => release.remote_id = RemoteId(dbus_str(&value, key).into()),
-                KEY_SIZE => release.size = dbus_u64(&value, key),
-                KEY_SOURCE_URL => release.source_url = Some(dbus_str(&value, key).into()),
-                KEY_SUMMARY => release.summary = dbus_str(&value, key).into(),
+                KEY_NAME => release.name = dbus_str(&value, key)?.into(),
+                KEY_PROTOCOL => release.protocol = Some(dbus_str(&value, key)?.into()),
+                KEY_REMOTE_ID => release.remote_id = RemoteId(dbus_str(&value, key)?.into()),
+                KEY_SIZE => release.size = dbus_u64(&value, key)?,
+                KEY_SOURCE_URL => release.source_url = Some(dbus_str(&value, key)?.into()),
+                KEY_SUMMARY => release.summary = dbus_str(&value, key)?.into(),
                 KEY_TRUST_FLAGS => {
-                    release.trust_flags = TrustFlags::from_bits_truncate(dbus_u64(&value, key))
+                    release.trust_flags = TrustFlags::from_bits_truncate(dbus_u64(&value, key)?)
+                }
+                KEY_UPDATE_MESSAGE => {
+                    release.update_message = Some(dbus_str(&value, key)?.into())
                 }
-                KEY_UPDATE_MESSAGE => release.update_message = Some(dbus_str(&value, key).into()),
-                KEY_URI => release.uri = dbus_str(&value, key).into(),
-                KEY_VENDOR => release.vendor = dbus_str(&value, key).into(),
-                KEY_VERSION => release.version = dbus_str(&value, key).into(),
+                KEY_URI => release.uri = dbus_str(&value, key)?.into(),
+                KEY_VENDOR => release.vendor = dbus_str(&value, key)?.into(),
+                KEY_VERSION => release.version = dbus_str(&value, key)?.into(),
                 other => {
-                    eprintln!("unknown release key: {} ({})", other, value.signature());
+                    debug!("unknown release key: {} ({}): {:?}", other, value.signature(), value);
+                    release.extra.insert(other.into(), Value::from(&*value.0));
                 }
             }
         }
 
-        release
+        Ok(release)
     }
 }
@@ -1,10 +1,15 @@
-use crate::{common::*, dbus_helpers::*, Client, DBusEntry};
+use crate::{
+    common::*, dbus_helpers::*, AppStreamError, Client, DBusEntries, HttpError, HttpTransport,
+    JcatError, JcatFile, Metadata, SignatureError, TrustRoots, Value,
+};
 use dbus::arg::RefArg;
 use std::{
     borrow::Cow,
+    collections::HashMap,
+    convert::TryFrom,
+    ffi::OsStr,
     fs::{metadata, File, OpenOptions},
-    io::{self, Seek, SeekFrom},
-    iter::FromIterator,
+    io::{self, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
@@ -17,6 +22,18 @@ pub enum KeyringKind {
     None,
     GPG,
     PKCS7,
+    Jcat,
+}
+
+impl KeyringKind {
+    /// The suffix of the signature file which accompanies the metadata of a remote.
+    pub fn signature_suffix(self) -> &'static str {
+        match self {
+            KeyringKind::PKCS7 => ".p7b",
+            KeyringKind::Jcat => ".jcat",
+            _ => ".asc",
+        }
+    }
 }
 
 impl From<u8> for KeyringKind {
@@ -27,6 +44,7 @@ impl From<u8> for KeyringKind {
             1 => None,
             2 => GPG,
             3 => PKCS7,
+            4 => Jcat,
             _ => Unknown,
         }
     }
@@ -67,11 +85,15 @@ pub enum UpdateError {
     #[error(display = "fwupd client errored when updating metadata for remote")]
     Client(#[error(cause, no_from)] crate::Error),
     #[error(display = "failed to copy firmware metadata from remote")]
-    Copy(#[error(cause, no_from)] reqwest::Error),
+    Copy(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to create parent directories for the remote's metadata cache")]
     CreateParent(#[error(cause, no_from)] io::Error),
     #[error(display = "remote returned error when fetching firmware metadata")]
-    Get(#[error(cause, no_from)] reqwest::Error),
+    Get(#[error(cause, no_from)] HttpError),
+    #[error(display = "the URI of the remote is invalid")]
+    InvalidUri(#[error(cause, no_from)] url::ParseError),
+    #[error(display = "the Jcat file of the remote does not match its firmware metadata")]
+    Jcat(#[error(cause, no_from)] JcatError),
     #[error(display = "attempted to update a remote without a URI")]
     NoUri,
     #[error(display = "unable to open cached firmware metadata ({:?}) for remote", _1)]
@@ -80,6 +102,8 @@ pub enum UpdateError {
     Read(#[error(cause, no_from)] io::Error, PathBuf),
     #[error(display = "failed to seek to beginning of firmware file")]
     Seek(#[error(cause, no_from)] io::Error),
+    #[error(display = "the signature of the firmware metadata could not be verified")]
+    Signature(#[error(cause, no_from)] SignatureError),
     #[error(display = "failed to truncate firmware metadata file")]
     Truncate(#[error(cause, no_from)] io::Error),
     #[error(display = "failed to get fwupd user agent")]
@@ -90,6 +114,10 @@ pub enum UpdateError {
 #[derive(Clone, Debug, Default, Eq, PartialEq, Shrinkwrap)]
 pub struct RemoteId(pub(crate) Box<str>);
 
+impl<'a> From<&'a str> for RemoteId {
+    fn from(id: &'a str) -> Self { RemoteId(id.into()) }
+}
+
 /// Information about an available fwupd remote.
 #[derive(Clone, Debug, Default)]
 pub struct Remote {
@@ -97,6 +125,8 @@ pub struct Remote {
     pub approval_required: bool,
     pub checksum:          Option<Box<str>>,
     pub enabled:           bool,
+    /// Keys from the daemon which are not recognized by this crate.
+    pub extra:             HashMap<Box<str>, Value>,
     pub filename_cache:    Box<str>,
     pub filename_source:   Box<str>,
     pub firmware_base_uri: Option<Box<str>>,
@@ -117,81 +147,128 @@ impl Remote {
     pub fn update_metadata(
         &self,
         client: &Client,
-        http_client: &reqwest::blocking::Client,
+        http_client: &dyn HttpTransport,
+    ) -> Result<(), UpdateError> {
+        self.update_metadata_with(client, http_client, None, &cache_dir())
+    }
+
+    /// Updates the metadata for this remote, after verifying its signature against the trust
+    /// roots.
+    ///
+    /// Metadata with a signature that cannot be verified is never sent to the daemon. Without
+    /// the `gpg` and `pkcs7` features, this fails for every remote with a GPG or PKCS#7 keyring.
+    pub fn update_metadata_verified(
+        &self,
+        client: &Client,
+        http_client: &dyn HttpTransport,
+        trust_roots: &TrustRoots,
+    ) -> Result<(), UpdateError> {
+        self.update_metadata_with(client, http_client, Some(trust_roots), &cache_dir())
+    }
+
+    /// Updates the metadata for this remote, which is cached within `cache_dir`.
+    pub(crate) fn update_metadata_with(
+        &self,
+        client: &Client,
+        http_client: &dyn HttpTransport,
+        trust_roots: Option<&TrustRoots>,
+        cache_dir: &Path,
     ) -> Result<(), UpdateError> {
         if !self.enabled {
             return Ok(());
         }
 
         let uri = self.uri.as_ref().ok_or(UpdateError::NoUri)?;
-        if let Some(file) = self.update_file(client, http_client, uri)? {
-            let sig = self.update_signature(client, http_client, uri)?;
+        if let Some(mut file) = self.update_file(client, http_client, uri, cache_dir)? {
+            let mut sig = self.update_signature(client, http_client, uri, cache_dir)?;
+
+            if trust_roots.is_some() || self.keyring == KeyringKind::Jcat {
+                let data_path = self.local_cache(cache_dir, self.filename_cache.as_ref());
+                let data = read_all(&mut file, &data_path)?;
+                let sig_path = self.local_cache(cache_dir, &self.signature_cache());
+                let signature = read_all(&mut sig, &sig_path)?;
+
+                if self.keyring == KeyringKind::Jcat {
+                    validate_jcat(uri, &data, &signature)?;
+                }
+
+                if let Some(trust_roots) = trust_roots {
+                    trust_roots
+                        .verify(self.keyring, &data, &signature)
+                        .map_err(UpdateError::Signature)?;
+                }
+            }
+
             client.update_metadata(&self, file, sig).map_err(UpdateError::Client)?;
         }
 
         Ok(())
     }
 
-    pub(crate) fn firmware_uri(&self, url: &str) -> Url {
+    /// The URI of firmware at a location of a release, which may be relative to the remote.
+    pub(crate) fn firmware_uri(&self, location: &str) -> Result<Url, crate::Error> {
+        let invalid = || crate::Error::FirmwareLocation(location.into());
+
         let uri = if let Some(ref firmware_base_uri) = self.firmware_base_uri {
             let mut firmware_base_uri: &str = firmware_base_uri;
             if firmware_base_uri.ends_with('/') {
                 firmware_base_uri = &firmware_base_uri[..firmware_base_uri.len() - 1];
             }
 
-            let basename = Path::new(url)
-                .file_name()
-                .expect("release URI without basename")
-                .to_str()
-                .expect("basename of release URI is not UTF-8");
+            let basename =
+                Path::new(location).file_name().and_then(OsStr::to_str).ok_or_else(invalid)?;
 
             Cow::Owned([firmware_base_uri, "/", basename].concat())
         // Use the base URI of the metadata to build the full path.
-        } else if !url.contains('/') {
-            let remote_uri: &str = self.uri.as_ref().expect("remote URI without URI");
-            let mut dirname = Path::new(remote_uri)
-                .parent()
-                .expect("metadata URI without parent")
-                .as_os_str()
-                .to_str()
-                .expect("metadata URI is not UTF-8");
+        } else if !location.contains('/') {
+            let remote_uri: &str = self.uri.as_ref().ok_or_else(invalid)?;
+            let mut dirname =
+                Path::new(remote_uri).parent().and_then(Path::to_str).ok_or_else(invalid)?;
 
             if dirname.ends_with('/') {
                 dirname = &dirname[..dirname.len() - 1];
             }
 
-            Cow::Owned([dirname, "/", url].concat())
+            Cow::Owned([dirname, "/", location].concat())
         // A normal URI
         } else {
-            Cow::Borrowed(url)
+            Cow::Borrowed(location)
         };
 
-        uri.parse::<Url>().expect("firmware uri is not a valid uri")
+        uri.parse::<Url>().map_err(|_| invalid())
+    }
+
+    /// Parses the metadata of this remote from the local cache, without asking the daemon.
+    ///
+    /// The metadata is cached by `update_metadata()`.
+    pub fn metadata(&self) -> Result<Metadata, AppStreamError> {
+        Metadata::open(self.local_cache(&cache_dir(), self.filename_cache.as_ref()))
     }
 
     /// Fetch the time since the last update, if such a time can be fetched.
     pub fn time_since_last_update(&self) -> Option<Duration> {
-        metadata(&self.local_cache(self.filename_cache.as_ref()))
+        metadata(&self.local_cache(&cache_dir(), self.filename_cache.as_ref()))
             .and_then(|md| md.modified())
             .ok()
             .and_then(|modified| SystemTime::now().duration_since(modified).ok())
     }
 
-    fn local_cache(&self, file: &str) -> PathBuf {
+    fn local_cache(&self, cache_dir: &Path, file: &str) -> PathBuf {
         let file_name =
             Path::new(file).file_name().expect("remote filename cache does not have a file name");
 
         let id: &str = &*self.remote_id;
-        cache_path(&Path::new(id).join(file_name))
+        cache_path(cache_dir, &Path::new(id).join(file_name))
     }
 
     fn update_file(
         &self,
         client: &Client,
-        http: &reqwest::blocking::Client,
+        http: &dyn HttpTransport,
         uri: &str,
+        cache_dir: &Path,
     ) -> Result<Option<File>, UpdateError> {
-        let local_cache = &self.local_cache(self.filename_cache.as_ref());
+        let local_cache = &self.local_cache(cache_dir, self.filename_cache.as_ref());
 
         if local_cache.exists() && self.checksum.is_some() {
             let mut file = OpenOptions::new()
@@ -216,17 +293,7 @@ impl Remote {
             .open(local_cache)
             .map_err(|why| UpdateError::Open(why, local_cache.to_path_buf()))?;
 
-        client
-            .get_request(http, uri)
-            .map_err(UpdateError::UserAgent)?
-            .send()
-            .map_err(UpdateError::Get)?
-            .error_for_status()
-            .map_err(UpdateError::Get)?
-            .copy_to(&mut file)
-            .map_err(UpdateError::Copy)?;
-
-        file.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
+        fetch_to(client, http, uri, &mut file)?;
 
         Ok(Some(file))
     }
@@ -234,10 +301,11 @@ impl Remote {
     fn update_signature(
         &self,
         client: &Client,
-        http: &reqwest::blocking::Client,
+        http: &dyn HttpTransport,
         uri: &str,
+        cache_dir: &Path,
     ) -> Result<File, UpdateError> {
-        let cache = &self.local_cache(&[self.filename_cache.as_ref(), ".asc"].concat());
+        let cache = &self.local_cache(cache_dir, &self.signature_cache());
 
         let mut file = OpenOptions::new()
             .read(true)
@@ -246,67 +314,95 @@ impl Remote {
             .open(cache)
             .map_err(|why| UpdateError::Open(why, cache.to_path_buf()))?;
 
-        client
-            .get_request(http, [uri, ".asc"].concat().as_str())
-            .map_err(UpdateError::UserAgent)?
-            .send()
-            .map_err(UpdateError::Get)?
-            .error_for_status()
-            .map_err(UpdateError::Get)?
-            .copy_to(&mut file)
-            .map_err(UpdateError::Copy)?;
-
-        file.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
+        let suffix = self.keyring.signature_suffix();
+        fetch_to(client, http, &[uri, suffix].concat(), &mut file)?;
 
         Ok(file)
     }
+
+    /// The name of the cached signature, whose suffix depends on the keyring of the remote.
+    fn signature_cache(&self) -> String {
+        [self.filename_cache.as_ref(), self.keyring.signature_suffix()].concat()
+    }
+}
+
+/// Validates the checksums of the Jcat item which describes the metadata at the URI.
+///
+/// The item is found by the file name of the metadata, which is the last segment of its path.
+pub(crate) fn validate_jcat(uri: &str, data: &[u8], jcat: &[u8]) -> Result<(), UpdateError> {
+    let uri = Url::parse(uri).map_err(UpdateError::InvalidUri)?;
+    let file = JcatFile::from_reader(jcat).map_err(UpdateError::Jcat)?;
+    let id = uri.path_segments().and_then(|segments| segments.last()).unwrap_or("");
+    let item = file.item(id).ok_or_else(|| UpdateError::Jcat(JcatError::NoItem(id.into())))?;
+    item.validate_checksums(data).map_err(UpdateError::Jcat)
+}
+
+/// Reads the whole file into memory, and then seeks back to its beginning.
+fn read_all(file: &mut File, path: &Path) -> Result<Vec<u8>, UpdateError> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|why| UpdateError::Read(why, path.to_path_buf()))?;
+    file.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
+    Ok(data)
+}
+
+/// Downloads the body of the URI into the file, and then seeks back to its beginning.
+fn fetch_to(
+    client: &Client,
+    http: &dyn HttpTransport,
+    uri: &str,
+    file: &mut File,
+) -> Result<(), UpdateError> {
+    let uri = Url::parse(uri).map_err(UpdateError::InvalidUri)?;
+    let request = client.get_request(uri).map_err(UpdateError::UserAgent)?;
+    let mut response =
+        http.get(&request).and_then(|r| r.error_for_status()).map_err(UpdateError::Get)?;
+
+    file.set_len(0).map_err(UpdateError::Truncate)?;
+    io::copy(&mut response.body, file).map_err(UpdateError::Copy)?;
+    file.seek(SeekFrom::Start(0)).map_err(UpdateError::Seek)?;
+
+    Ok(())
 }
 
 impl AsRef<RemoteId> for Remote {
     fn as_ref(&self) -> &RemoteId { &self.remote_id }
 }
 
-impl FromIterator<DBusEntry> for Remote {
-    fn from_iter<T>(iter: T) -> Self
-    where
-        T: IntoIterator<Item = DBusEntry>,
-    {
+impl TryFrom<DBusEntries> for Remote {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
         let mut remote = Remote::default();
 
-        for (key, value) in iter {
+        for (key, value) in entries {
             let key = key.as_str();
             match key {
-                "Agreement" => remote.agreement = Some(dbus_str(&value, key).into()),
-                "ApprovalRequired" => remote.approval_required = dbus_u64(&value, key) != 0,
-                KEY_CHECKSUM => remote.checksum = Some(dbus_str(&value, key).into()),
-                "Enabled" => remote.enabled = dbus_u64(&value, key) != 0,
-                "FilenameCache" => remote.filename_cache = dbus_str(&value, key).into(),
-                "FilenameSource" => remote.filename_source = dbus_str(&value, key).into(),
-                "FirmwareBaseUri" => remote.firmware_base_uri = Some(dbus_str(&value, key).into()),
-                "Keyring" => remote.keyring = KeyringKind::from(dbus_u64(&value, key) as u8),
-                "ModificationTime" => remote.modification_time = dbus_u64(&value, key),
-                "Password" => remote.password = Some(dbus_str(&value, key).into()),
-                "Priority" => {
-                    let value = value
-                        .as_iter()
-                        .expect("Priority is not a variant")
-                        .next()
-                        .expect("Priority does not contain a value");
-
-                    remote.priority = dbus_i64(&value, key) as i16;
+                "Agreement" => remote.agreement = Some(dbus_str(&value, key)?.into()),
+                "ApprovalRequired" => remote.approval_required = dbus_u64(&value, key)? != 0,
+                KEY_CHECKSUM => remote.checksum = Some(dbus_str(&value, key)?.into()),
+                "Enabled" => remote.enabled = dbus_u64(&value, key)? != 0,
+                "FilenameCache" => remote.filename_cache = dbus_str(&value, key)?.into(),
+                "FilenameSource" => remote.filename_source = dbus_str(&value, key)?.into(),
+                "FirmwareBaseUri" => {
+                    remote.firmware_base_uri = Some(dbus_str(&value, key)?.into())
                 }
-                KEY_REMOTE_ID => remote.remote_id = RemoteId(dbus_str(&value, key).into()),
-                "ReportUri" => remote.report_uri = Some(dbus_str(&value, key).into()),
-                "Title" => remote.title = dbus_str(&value, key).into(),
-                "Type" => remote.kind = RemoteKind::from(dbus_u64(&value, key) as u8),
-                "Username" => remote.username = Some(dbus_str(&value, key).into()),
-                KEY_URI => remote.uri = Some(dbus_str(&value, key).into()),
+                "Keyring" => remote.keyring = KeyringKind::from(dbus_u64(&value, key)? as u8),
+                "ModificationTime" => remote.modification_time = dbus_u64(&value, key)?,
+                "Password" => remote.password = Some(dbus_str(&value, key)?.into()),
+                "Priority" => remote.priority = dbus_i64(&value, key)? as i16,
+                KEY_REMOTE_ID => remote.remote_id = RemoteId(dbus_str(&value, key)?.into()),
+                "ReportUri" => remote.report_uri = Some(dbus_str(&value, key)?.into()),
+                "Title" => remote.title = dbus_str(&value, key)?.into(),
+                "Type" => remote.kind = RemoteKind::from(dbus_u64(&value, key)? as u8),
+                "Username" => remote.username = Some(dbus_str(&value, key)?.into()),
+                KEY_URI => remote.uri = Some(dbus_str(&value, key)?.into()),
                 other => {
-                    eprintln!("unknown remote key: {} ({}): {:?}", other, value.signature(), value);
+                    debug!("unknown remote key: {} ({}): {:?}", other, value.signature(), value);
+                    remote.extra.insert(other.into(), Value::from(&*value.0));
                 }
             }
         }
 
-        remote
+        Ok(remote)
     }
 }
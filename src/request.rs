@@ -0,0 +1,133 @@
+use crate::{common::*, dbus_helpers::*, DBusEntries, DeviceId, Value};
+use dbus::arg::RefArg;
+use std::{collections::HashMap, convert::TryFrom};
+
+/// Describes when the user should act upon a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum RequestKind {
+    Unknown,
+    /// After the update has completed.
+    Post,
+    /// Immediately, as the update is waiting for the user.
+    Immediate,
+}
+
+impl Default for RequestKind {
+    fn default() -> Self { RequestKind::Unknown }
+}
+
+impl From<u8> for RequestKind {
+    fn from(value: u8) -> Self {
+        use self::RequestKind::*;
+        match value {
+            1 => Post,
+            2 => Immediate,
+            _ => Unknown,
+        }
+    }
+}
+
+/// The well-known IDs of requests, which a client may show its own instructions for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequestId {
+    /// Remove the device, and then plug it back in.
+    RemoveReplug,
+    /// Press the unlock button on the device.
+    PressUnlock,
+    /// Do not turn off the machine, or unplug the device.
+    DoNotPowerOff,
+    /// Unplug the device, and then plug it back in to install the firmware.
+    ReplugInstall,
+    /// Insert the USB cable.
+    InsertUsbCable,
+    /// Remove the USB cable.
+    RemoveUsbCable,
+    /// Unplug the power of the device, and then plug it back in.
+    ReplugPower,
+    /// Restart the daemon.
+    RestartDaemon,
+}
+
+impl RequestId {
+    /// The ID which the daemon sends for this request.
+    pub fn as_str(self) -> &'static str {
+        use self::RequestId::*;
+        match self {
+            RemoveReplug => "org.freedesktop.fwupd.request.remove-replug",
+            PressUnlock => "org.freedesktop.fwupd.request.press-unlock",
+            DoNotPowerOff => "org.freedesktop.fwupd.request.do-not-power-off",
+            ReplugInstall => "org.freedesktop.fwupd.replug-install",
+            InsertUsbCable => "org.freedesktop.fwupd.request.insert-usb-cable",
+            RemoveUsbCable => "org.freedesktop.fwupd.request.remove-usb-cable",
+            ReplugPower => "org.freedesktop.fwupd.request.replug-power",
+            RestartDaemon => "org.freedesktop.fwupd.restart-daemon",
+        }
+    }
+
+    /// Matches an ID sent by the daemon to a well-known request.
+    pub fn from_id(id: &str) -> Option<Self> {
+        use self::RequestId::*;
+        [
+            RemoveReplug,
+            PressUnlock,
+            DoNotPowerOff,
+            ReplugInstall,
+            InsertUsbCable,
+            RemoveUsbCable,
+            ReplugPower,
+            RestartDaemon,
+        ]
+        .iter()
+        .cloned()
+        .find(|request| request.as_str() == id)
+    }
+}
+
+/// A request from the daemon for the user to perform an action, such as replugging a device.
+#[derive(Clone, Debug, Default)]
+pub struct Request {
+    pub created:   u64,
+    pub device_id: DeviceId,
+    /// Keys from the daemon which are not recognized by this crate.
+    pub extra:     HashMap<Box<str>, Value>,
+    pub id:        Box<str>,
+    /// The URL of an image which shows the user what to do.
+    pub image:     Option<Box<str>>,
+    pub kind:      RequestKind,
+    /// Instructions to show to the user.
+    pub message:   Option<Box<str>>,
+}
+
+impl Request {
+    /// The well-known request that the ID of this request refers to.
+    pub fn well_known_id(&self) -> Option<RequestId> { RequestId::from_id(&self.id) }
+}
+
+impl TryFrom<DBusEntries> for Request {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
+        let mut request = Request::default();
+
+        for (key, value) in entries {
+            let key = key.as_str();
+            match key {
+                KEY_APPSTREAM_ID => request.id = dbus_str(&value, key)?.into(),
+                KEY_CREATED => request.created = dbus_u64(&value, key)?,
+                KEY_DEVICE_ID => request.device_id = DeviceId(dbus_str(&value, key)?.into()),
+                KEY_REQUEST_KIND => {
+                    request.kind = RequestKind::from(dbus_u64(&value, key)? as u8)
+                }
+                KEY_UPDATE_IMAGE => request.image = Some(dbus_str(&value, key)?.into()),
+                KEY_UPDATE_MESSAGE => request.message = Some(dbus_str(&value, key)?.into()),
+                other => {
+                    debug!("unknown request key: {} ({}): {:?}", other, value.signature(), value);
+                    request.extra.insert(other.into(), Value::from(&*value.0));
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{common::*, dbus_helpers::*, DBusEntries, Value};
+use dbus::arg::RefArg;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// The highest HSI level that an attribute may belong to.
+const HSI_LEVEL_LAST: u32 = 5;
+
+bitflags! {
+    /// Describes attributes of a host security attribute.
+    pub struct SecurityAttrFlags: u64 {
+        /// The attribute was tested, and passed
+        const SUCCESS             = 1;
+        /// The attribute was superseded by another attribute
+        const OBSOLETED           = 1 << 1;
+        /// The attribute relates to the runtime being kept up to date
+        const RUNTIME_UPDATES     = 1 << 8;
+        /// The attribute relates to the runtime being attested
+        const RUNTIME_ATTESTATION = 1 << 9;
+        /// The attribute relates to an issue with the runtime
+        const RUNTIME_ISSUE       = 1 << 10;
+    }
+}
+
+impl Default for SecurityAttrFlags {
+    fn default() -> Self { SecurityAttrFlags::empty() }
+}
+
+/// Describes the result of testing a host security attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum SecurityAttrResult {
+    Unknown,
+    Enabled,
+    NotEnabled,
+    Valid,
+    NotValid,
+    Locked,
+    NotLocked,
+    Encrypted,
+    NotEncrypted,
+    Tainted,
+    NotTainted,
+    Found,
+    NotFound,
+    Supported,
+    NotSupported,
+}
+
+impl Default for SecurityAttrResult {
+    fn default() -> Self { SecurityAttrResult::Unknown }
+}
+
+impl From<u8> for SecurityAttrResult {
+    fn from(value: u8) -> Self {
+        use self::SecurityAttrResult::*;
+        match value {
+            1 => Enabled,
+            2 => NotEnabled,
+            3 => Valid,
+            4 => NotValid,
+            5 => Locked,
+            6 => NotLocked,
+            7 => Encrypted,
+            8 => NotEncrypted,
+            9 => Tainted,
+            10 => NotTainted,
+            11 => Found,
+            12 => NotFound,
+            13 => Supported,
+            14 => NotSupported,
+            _ => Unknown,
+        }
+    }
+}
+
+/// A host security attribute, which was tested by a plugin of the daemon.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityAttr {
+    pub appstream_id: Box<str>,
+    pub created:      u64,
+    /// Keys from the daemon which are not recognized by this crate.
+    pub extra:        HashMap<Box<str>, Value>,
+    pub flags:        SecurityAttrFlags,
+    pub guids:        Box<[Box<str>]>,
+    pub hsi_level:    u32,
+    pub name:         Box<str>,
+    pub obsoletes:    Box<[Box<str>]>,
+    pub plugin:       Box<str>,
+    pub result:       SecurityAttrResult,
+    pub url:          Option<Box<str>>,
+}
+
+impl SecurityAttr {
+    pub fn has_flag(&self, flags: SecurityAttrFlags) -> bool { self.flags.contains(flags) }
+
+    /// If the attribute was tested, and passed.
+    pub fn is_success(&self) -> bool { self.has_flag(SecurityAttrFlags::SUCCESS) }
+
+    /// If the attribute was superseded by another attribute.
+    pub fn is_obsoleted(&self) -> bool { self.has_flag(SecurityAttrFlags::OBSOLETED) }
+}
+
+impl TryFrom<DBusEntries> for SecurityAttr {
+    type Error = DecodeError;
+
+    fn try_from(entries: DBusEntries) -> Result<Self, Self::Error> {
+        let mut attr = SecurityAttr::default();
+
+        for (key, value) in entries {
+            let key = key.as_str();
+            match key {
+                KEY_APPSTREAM_ID => attr.appstream_id = dbus_str(&value, key)?.into(),
+                KEY_CREATED => attr.created = dbus_u64(&value, key)?,
+                KEY_FLAGS => {
+                    attr.flags = SecurityAttrFlags::from_bits_truncate(dbus_u64(&value, key)?)
+                }
+                KEY_GUID => attr.guids = dbus_str_array(&value, key)?,
+                KEY_HSI_LEVEL => attr.hsi_level = dbus_u64(&value, key)? as u32,
+                KEY_HSI_RESULT => {
+                    attr.result = SecurityAttrResult::from(dbus_u64(&value, key)? as u8)
+                }
+                KEY_NAME => attr.name = dbus_str(&value, key)?.into(),
+                KEY_OBSOLETES => attr.obsoletes = dbus_str_array(&value, key)?,
+                KEY_PLUGIN => attr.plugin = dbus_str(&value, key)?.into(),
+                KEY_URI => attr.url = Some(dbus_str(&value, key)?.into()),
+                other => {
+                    debug!("unknown attr key: {} ({}): {:?}", other, value.signature(), value);
+                    attr.extra.insert(other.into(), Value::from(&*value.0));
+                }
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+/// The host security ID, as displayed by `fwupdmgr security`, such as `HSI:2!`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HostSecurityId {
+    /// The highest level where no attribute failed, and at least one attribute passed.
+    pub level:           u32,
+    /// An attribute found an issue with the runtime, which is displayed as `!`.
+    pub runtime_issue:   bool,
+    /// An attribute found that the runtime is kept up to date or attested, which is displayed
+    /// as `+`.
+    pub runtime_success: bool,
+}
+
+impl HostSecurityId {
+    /// Calculates the host security ID from the attributes reported by the daemon.
+    ///
+    /// Based on `fu_security_attrs_calculate_hsi` in fwupd.
+    pub fn calculate(attrs: &[SecurityAttr]) -> Self {
+        let mut hsi = HostSecurityId::default();
+
+        for level in 1..=HSI_LEVEL_LAST {
+            let attrs = attrs.iter().filter(|attr| attr.hsi_level == level);
+            let (mut success, mut failure) = (false, false);
+            for attr in attrs {
+                if attr.is_success() {
+                    success = true;
+                } else if !attr.is_obsoleted() {
+                    failure = true;
+                }
+            }
+
+            if failure {
+                hsi.level = level - 1;
+                break;
+            }
+
+            if success {
+                hsi.level = level;
+            }
+        }
+
+        let runtime = SecurityAttrFlags::RUNTIME_UPDATES | SecurityAttrFlags::RUNTIME_ATTESTATION;
+        for attr in attrs.iter().filter(|attr| !attr.is_obsoleted()) {
+            if attr.flags.intersects(runtime) && attr.is_success() {
+                hsi.runtime_success = true;
+            }
+
+            if attr.has_flag(SecurityAttrFlags::RUNTIME_ISSUE) && !attr.is_success() {
+                hsi.runtime_issue = true;
+            }
+        }
+
+        hsi
+    }
+}
+
+impl Display for HostSecurityId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "HSI:{}", self.level)?;
+
+        if self.runtime_issue {
+            f.write_str("!")?;
+        }
+
+        if self.runtime_success {
+            f.write_str("+")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{DBusEntries, DecodeError, Device, Error, Request, Status, Value, DBUS_IFACE};
+use dbus::{arg::RefArg, Message};
+use std::convert::TryFrom;
+
+/// Signal received by the daemon when listening for signal events with `Client::listen_signals()`.
+#[derive(Debug)]
+pub enum Signal {
+    /// Some value on the interface or the number of devices or profiles has changed.
+    Changed,
+    /// A device has been added.
+    DeviceAdded(Device),
+    /// A device has been changed.
+    DeviceChanged(Device),
+    /// A device has been removed.
+    DeviceRemoved(Device),
+    /// The user must perform an action for a device, such as replugging it.
+    DeviceRequest(Request),
+    /// The version of the daemon has changed.
+    DaemonVersionChanged(Box<str>),
+    /// The job percentage completion has changed.
+    PercentageChanged(u8),
+    /// The daemon status has changed.
+    StatusChanged(Status),
+    /// The daemon has been tainted, or is no longer tainted, by a third party plugin.
+    TaintedChanged(bool),
+    /// A property of the daemon which does not have a typed signal has changed.
+    PropertyChanged(Box<str>, Value),
+    /// A property of the daemon has changed, but its new value was not sent with the signal.
+    PropertyInvalidated(Box<str>),
+}
+
+impl Signal {
+    /// Converts a changed property of the daemon into its typed signal, or else into a
+    /// `PropertyChanged` signal which carries the value as it was received.
+    fn from_property(property: &str, value: &dyn RefArg) -> Self {
+        let signal = match property {
+            "DaemonVersion" => value.as_str().map(|v| Signal::DaemonVersionChanged(v.into())),
+            "Percentage" => value.as_u64().map(|v| Signal::PercentageChanged(v as u8)),
+            "Status" => value.as_u64().map(|v| Signal::StatusChanged(Status::from(v as u8))),
+            "Tainted" => value.as_u64().map(|v| Signal::TaintedChanged(v != 0)),
+            _ => None,
+        };
+
+        signal.unwrap_or_else(|| Signal::PropertyChanged(property.into(), Value::from(value)))
+    }
+}
+
+/// Parses the signals carried by a message received from the daemon.
+///
+/// A single `PropertiesChanged` message may yield multiple signals.
+pub(crate) fn parse(message: Message) -> Vec<Signal> {
+    fn read_signal<T: TryFrom<DBusEntries, Error = DecodeError>>(
+        signal: Message,
+        method: &'static str,
+    ) -> Result<T, Error> {
+        let entries: DBusEntries =
+            signal.read1().map_err(|why| Error::ArgumentMismatch(method, why))?;
+
+        T::try_from(entries).map_err(|why| Error::Decode(method, why))
+    }
+
+    let member = match message.member() {
+        Some(member) => member,
+        None => return Vec::new(),
+    };
+
+    let signals = match &*member {
+        "Changed" => Ok(vec![Signal::Changed]),
+        "DeviceAdded" => read_signal(message, "DeviceAdded").map(|d| vec![Signal::DeviceAdded(d)]),
+        "DeviceChanged" => {
+            read_signal(message, "DeviceChanged").map(|d| vec![Signal::DeviceChanged(d)])
+        }
+        "DeviceRemoved" => {
+            read_signal(message, "DeviceRemoved").map(|d| vec![Signal::DeviceRemoved(d)])
+        }
+        "DeviceRequest" => {
+            read_signal(message, "DeviceRequest").map(|r| vec![Signal::DeviceRequest(r)])
+        }
+        "PropertiesChanged" => message
+            .read3::<String, DBusEntries, Vec<String>>()
+            .map_err(|why| Error::ArgumentMismatch("PropertiesChanged", why))
+            .map(|(interface, changed, invalidated)| {
+                if interface != DBUS_IFACE {
+                    return Vec::new();
+                }
+
+                changed
+                    .iter()
+                    .map(|(property, value)| Signal::from_property(property, &*value.0))
+                    .chain(
+                        invalidated
+                            .into_iter()
+                            .map(|property| Signal::PropertyInvalidated(property.into())),
+                    )
+                    .collect()
+            }),
+        _ => return Vec::new(),
+    };
+
+    signals.unwrap_or_else(|why| {
+        error!("signal error: {}", why);
+        Vec::new()
+    })
+}
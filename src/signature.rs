@@ -0,0 +1,290 @@
+//! Verifies the detached signatures of remote metadata on the client, before the metadata is
+//! passed to the daemon.
+
+use crate::{JcatError, JcatFile, KeyringKind};
+#[cfg(any(feature = "gpg", feature = "pkcs7"))]
+use std::{fs, path::Path};
+use std::{io, path::PathBuf};
+
+/// The directory that fwupd installs the trust roots of remote metadata to.
+pub const TRUST_ROOTS_DIR: &str = "/etc/pki/fwupd-metadata";
+
+/// An error that may occur when verifying a signature.
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error(display = "signature is not valid: {}", _0)]
+    Invalid(Box<str>),
+    #[error(display = "the Jcat file of the signature is invalid")]
+    Jcat(#[error(cause, no_from)] JcatError),
+    #[error(display = "no trust roots for the {:?} keyring were found in {:?}", _0, _1)]
+    NoTrustRoots(KeyringKind, PathBuf),
+    #[error(display = "unable to read the trust roots in {:?}", _1)]
+    ReadRoots(#[error(cause, no_from)] io::Error, PathBuf),
+    #[error(display = "signatures of the {:?} keyring are not supported by this build", _0)]
+    Unsupported(KeyringKind),
+    #[error(display = "the signature was not made by any of the trust roots")]
+    Untrusted,
+}
+
+/// The keys and certificates which signatures of remote metadata are verified against.
+///
+/// GPG keys are read from ASCII-armored files, and PKCS#7 certificates from PEM files. Files
+/// which do not contain either are ignored.
+#[derive(Clone, Debug)]
+pub struct TrustRoots {
+    pub path: PathBuf,
+}
+
+impl Default for TrustRoots {
+    fn default() -> Self { Self::new(TRUST_ROOTS_DIR) }
+}
+
+impl TrustRoots {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self { Self { path: path.into() } }
+
+    /// Verifies the detached signature of the data, for a remote with the given keyring.
+    ///
+    /// A remote without a keyring has nothing to verify, and always succeeds. A Jcat signature
+    /// succeeds if any of its GPG or PKCS#7 blobs verifies the data.
+    ///
+    /// GPG and PKCS#7 signatures fail with `SignatureError::Unsupported` unless the `gpg` and
+    /// `pkcs7` features are enabled.
+    pub fn verify(
+        &self,
+        keyring: KeyringKind,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), SignatureError> {
+        match keyring {
+            KeyringKind::None => Ok(()),
+            #[cfg(feature = "gpg")]
+            KeyringKind::GPG => gpg::verify(&self.read()?, data, signature, &self.path),
+            #[cfg(feature = "pkcs7")]
+            KeyringKind::PKCS7 => pkcs7::verify(&self.read()?, data, signature, &self.path),
+            KeyringKind::Jcat => self.verify_jcat(data, signature),
+            other => Err(SignatureError::Unsupported(other)),
+        }
+    }
+
+    /// Verifies the signature blobs of the Jcat item whose checksums match the data.
+    fn verify_jcat(&self, data: &[u8], jcat: &[u8]) -> Result<(), SignatureError> {
+        let file = JcatFile::from_reader(jcat).map_err(SignatureError::Jcat)?;
+
+        let mut result = Err(SignatureError::Untrusted);
+        for item in &file.items {
+            if let Err(why) = item.validate_checksums(data) {
+                result = Err(SignatureError::Jcat(why));
+                continue;
+            }
+
+            for (keyring, signature) in item.signatures() {
+                match self.verify(keyring, data, signature) {
+                    Ok(()) => return Ok(()),
+                    Err(why) => result = Err(why),
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Reads every file in the directory of trust roots.
+    #[cfg(any(feature = "gpg", feature = "pkcs7"))]
+    fn read(&self) -> Result<Vec<Vec<u8>>, SignatureError> {
+        let error = |why| SignatureError::ReadRoots(why, self.path.clone());
+
+        let mut roots = Vec::new();
+        for entry in fs::read_dir(&self.path).map_err(error)? {
+            let path = entry.map_err(error)?.path();
+            if path.is_file() {
+                roots.push(fs::read(&path).map_err(error)?);
+            }
+        }
+
+        Ok(roots)
+    }
+}
+
+#[cfg(feature = "gpg")]
+mod gpg {
+    use super::*;
+    use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+    use std::io::Cursor;
+
+    pub fn verify(
+        roots: &[Vec<u8>],
+        data: &[u8],
+        signature: &[u8],
+        path: &Path,
+    ) -> Result<(), SignatureError> {
+        let keys: Vec<SignedPublicKey> = roots
+            .iter()
+            .filter_map(|root| SignedPublicKey::from_armor_single(Cursor::new(root)).ok())
+            .map(|(key, _)| key)
+            .collect();
+
+        if keys.is_empty() {
+            return Err(SignatureError::NoTrustRoots(KeyringKind::GPG, path.to_path_buf()));
+        }
+
+        let (signature, _) = StandaloneSignature::from_armor_single(Cursor::new(signature))
+            .map_err(|why| SignatureError::Invalid(why.to_string().into()))?;
+
+        let verified = keys.iter().any(|key| {
+            signature.verify(key, data).is_ok()
+                || key.public_subkeys.iter().any(|subkey| signature.verify(subkey, data).is_ok())
+        });
+
+        if verified {
+            Ok(())
+        } else {
+            Err(SignatureError::Untrusted)
+        }
+    }
+}
+
+#[cfg(feature = "pkcs7")]
+mod pkcs7 {
+    use super::*;
+    use openssl::{
+        pkcs7::{Pkcs7, Pkcs7Flags},
+        stack::Stack,
+        x509::{store::X509StoreBuilder, X509},
+    };
+
+    pub fn verify(
+        roots: &[Vec<u8>],
+        data: &[u8],
+        signature: &[u8],
+        path: &Path,
+    ) -> Result<(), SignatureError> {
+        let invalid =
+            |why: openssl::error::ErrorStack| SignatureError::Invalid(why.to_string().into());
+
+        let certs: Vec<X509> =
+            roots.iter().filter_map(|root| X509::stack_from_pem(root).ok()).flatten().collect();
+
+        if certs.is_empty() {
+            return Err(SignatureError::NoTrustRoots(KeyringKind::PKCS7, path.to_path_buf()));
+        }
+
+        let mut store = X509StoreBuilder::new().map_err(invalid)?;
+        for cert in certs {
+            store.add_cert(cert).map_err(invalid)?;
+        }
+
+        let store = store.build();
+        let signature = Pkcs7::from_pem(signature)
+            .or_else(|_| Pkcs7::from_der(signature))
+            .map_err(invalid)?;
+
+        let signers = Stack::new().map_err(invalid)?;
+        signature
+            .verify(&signers, &store, Some(data), None, Pkcs7Flags::BINARY)
+            .map_err(|_| SignatureError::Untrusted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::temp_dir;
+
+    #[test]
+    fn trust_roots_without_keys() {
+        let roots = TrustRoots::new(temp_dir("empty-trust-roots"));
+
+        assert!(roots.verify(KeyringKind::None, b"metadata", b"").is_ok());
+        assert!(roots.verify(KeyringKind::GPG, b"metadata", b"signature").is_err());
+        assert!(roots.verify(KeyringKind::PKCS7, b"metadata", b"signature").is_err());
+
+        let _ = std::fs::remove_dir_all(&roots.path);
+    }
+
+    /// An Ed25519 key, and its detached signature of `metadata`.
+    #[cfg(feature = "gpg")]
+    const GPG_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEXgvhABYJKwYBBAHaRw8BAQdAktdVxepF6KJk66aNXs9YS460CcxTDoELDSqC
+Sc+G0nu0JmZ3dXBkLWRidXMgdGVzdCA8dGVzdEBmd3VwZC1kYnVzLnRlc3Q+iJAE
+ExYIADgWIQRPdfCBHoKgk7lW3lZzdOztg1xXYQUCXgvhAAIbAwULCQgHAgYVCgkI
+CwIEFgIDAQIeAQIXgAAKCRBzdOztg1xXYWzCAPwN5fE3xMMsHk5600sXIXgDY2v0
+wu7KWFO7jdOGdnOrFwD+Ix41xXWqIBzC4vWYebyOKw4LUXdSJf1yhwKdkDwGzwQ=
+=uOVI
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    #[cfg(feature = "gpg")]
+    const GPG_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----
+
+iHUEABYIAB0WIQRPdfCBHoKgk7lW3lZzdOztg1xXYQUCXgvhAAAKCRBzdOztg1xX
+YepVAP9sy5W92ffDQ00chDpPrxFNk4hX+VPGuBaijE2iH8PPCAEA0wpWtJhXa3wO
+3d+9tQRN/UJtyyP6zkt7tOxILw24kgs=
+=h5CG
+-----END PGP SIGNATURE-----
+";
+
+    #[cfg(feature = "gpg")]
+    #[test]
+    fn trust_roots_gpg() {
+        let roots = TrustRoots::new(temp_dir("gpg-trust-roots"));
+        std::fs::write(roots.path.join("test.asc"), GPG_KEY).unwrap();
+
+        assert!(roots.verify(KeyringKind::GPG, b"metadata", GPG_SIGNATURE.as_bytes()).is_ok());
+        match roots.verify(KeyringKind::GPG, b"tampered", GPG_SIGNATURE.as_bytes()) {
+            Err(SignatureError::Untrusted) => (),
+            other => panic!("expected the signature to be untrusted, found {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&roots.path);
+    }
+
+    /// A self-signed P-256 certificate, and its detached signature of `metadata`.
+    #[cfg(feature = "pkcs7")]
+    const PKCS7_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBizCCATGgAwIBAgIUYBstUFe6/pRmAtVLmSHRfGXftLAwCgYIKoZIzj0EAwIw
+GjEYMBYGA1UEAwwPZnd1cGQtZGJ1cyB0ZXN0MCAXDTAwMDEwMTAwMDAwMFoYDzIx
+MDAwMTAxMDAwMDAwWjAaMRgwFgYDVQQDDA9md3VwZC1kYnVzIHRlc3QwWTATBgcq
+hkjOPQIBBggqhkjOPQMBBwNCAATB4BNxr8tWl5eJAz4kTAwmDhXEfX28c2EgZzIU
+99B3s6VHhfSo6QJLsx2tX2+DNs2K1Yz3RxWnTzDWSq+fkyzso1MwUTAdBgNVHQ4E
+FgQUeBazvOKTau14FIlSNHR31p3pgxMwHwYDVR0jBBgwFoAUeBazvOKTau14FIlS
+NHR31p3pgxMwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiAdeYIs
+0PtoyNBo3/w2dfWZOdbSs14HagDMwwaMji2VvAIhAIzYdJI4u7QzK6h4PDxlCmVN
+P0NbO7XLVDnBwgBgnW0r
+-----END CERTIFICATE-----
+";
+
+    #[cfg(feature = "pkcs7")]
+    const PKCS7_SIGNATURE: &str = "-----BEGIN PKCS7-----
+MIICaAYJKoZIhvcNAQcCoIICWTCCAlUCAQExDzANBglghkgBZQMEAgEFADALBgkq
+hkiG9w0BBwGgggGPMIIBizCCATGgAwIBAgIUYBstUFe6/pRmAtVLmSHRfGXftLAw
+CgYIKoZIzj0EAwIwGjEYMBYGA1UEAwwPZnd1cGQtZGJ1cyB0ZXN0MCAXDTAwMDEw
+MTAwMDAwMFoYDzIxMDAwMTAxMDAwMDAwWjAaMRgwFgYDVQQDDA9md3VwZC1kYnVz
+IHRlc3QwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAATB4BNxr8tWl5eJAz4kTAwm
+DhXEfX28c2EgZzIU99B3s6VHhfSo6QJLsx2tX2+DNs2K1Yz3RxWnTzDWSq+fkyzs
+o1MwUTAdBgNVHQ4EFgQUeBazvOKTau14FIlSNHR31p3pgxMwHwYDVR0jBBgwFoAU
+eBazvOKTau14FIlSNHR31p3pgxMwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQD
+AgNIADBFAiAdeYIs0PtoyNBo3/w2dfWZOdbSs14HagDMwwaMji2VvAIhAIzYdJI4
+u7QzK6h4PDxlCmVNP0NbO7XLVDnBwgBgnW0rMYGeMIGbAgEBMDIwGjEYMBYGA1UE
+AwwPZnd1cGQtZGJ1cyB0ZXN0AhRgGy1QV7r+lGYC1UuZIdF8Zd+0sDANBglghkgB
+ZQMEAgEFADAKBggqhkjOPQQDAgRHMEUCIQDlXCIO0y7BDpEjiSYwjb60exo1voUm
+nVTjznV9vy6NHQIgWhd3M2iX5ZjZntwdDKcWBW8yXy/rchShmmwwJO0Qi9g=
+-----END PKCS7-----
+";
+
+    #[cfg(feature = "pkcs7")]
+    #[test]
+    fn trust_roots_pkcs7() {
+        let roots = TrustRoots::new(temp_dir("pkcs7-trust-roots"));
+        std::fs::write(roots.path.join("test.pem"), PKCS7_CERT).unwrap();
+
+        let signature = PKCS7_SIGNATURE.as_bytes();
+        assert!(roots.verify(KeyringKind::PKCS7, b"metadata", signature).is_ok());
+        match roots.verify(KeyringKind::PKCS7, b"tampered", signature) {
+            Err(SignatureError::Untrusted) => (),
+            other => panic!("expected the signature to be untrusted, found {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&roots.path);
+    }
+}
@@ -0,0 +1,114 @@
+use dbus::arg::{ArgType, RefArg};
+
+/// An owned copy of a value received from the daemon, for keys this crate does not recognize.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Byte(u8),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    Str(Box<str>),
+    ObjectPath(Box<str>),
+    Signature(Box<str>),
+    Array(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+    /// A value which cannot be owned, such as a file descriptor, with its signature.
+    Unsupported(Box<str>),
+}
+
+impl Value {
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(value) => Some(value),
+            Value::Variant(ref value) => value.as_bool(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Byte(value) => Some(i64::from(value)),
+            Value::Int16(value) => Some(i64::from(value)),
+            Value::UInt16(value) => Some(i64::from(value)),
+            Value::Int32(value) => Some(i64::from(value)),
+            Value::UInt32(value) => Some(i64::from(value)),
+            Value::Int64(value) => Some(value),
+            Value::Variant(ref value) => value.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Byte(value) => Some(u64::from(value)),
+            Value::UInt16(value) => Some(u64::from(value)),
+            Value::UInt32(value) => Some(u64::from(value)),
+            Value::UInt64(value) => Some(value),
+            Value::Variant(ref value) => value.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref value) | Value::ObjectPath(ref value) => Some(value),
+            Value::Variant(ref value) => value.as_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_slice(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) | Value::Struct(ref values) => Some(values),
+            Value::Variant(ref value) => value.as_slice(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a dyn RefArg> for Value {
+    fn from(arg: &'a dyn RefArg) -> Self {
+        fn collect(arg: &dyn RefArg) -> Vec<Value> {
+            arg.as_iter().map(|iter| iter.map(Value::from).collect()).unwrap_or_default()
+        }
+
+        match arg.arg_type() {
+            ArgType::Boolean => Value::Bool(arg.as_u64().map_or(false, |v| v != 0)),
+            ArgType::Byte => Value::Byte(arg.as_u64().unwrap_or(0) as u8),
+            ArgType::Int16 => Value::Int16(arg.as_i64().unwrap_or(0) as i16),
+            ArgType::UInt16 => Value::UInt16(arg.as_u64().unwrap_or(0) as u16),
+            ArgType::Int32 => Value::Int32(arg.as_i64().unwrap_or(0) as i32),
+            ArgType::UInt32 => Value::UInt32(arg.as_u64().unwrap_or(0) as u32),
+            ArgType::Int64 => Value::Int64(arg.as_i64().unwrap_or(0)),
+            ArgType::UInt64 => Value::UInt64(arg.as_u64().unwrap_or(0)),
+            ArgType::Double => Value::Double(arg.as_f64().unwrap_or(0.0)),
+            ArgType::String => Value::Str(arg.as_str().unwrap_or("").into()),
+            ArgType::ObjectPath => Value::ObjectPath(arg.as_str().unwrap_or("").into()),
+            ArgType::Signature => Value::Signature(arg.as_str().unwrap_or("").into()),
+            ArgType::Array if arg.signature().starts_with("a{") => {
+                // Dictionaries are iterated as alternating keys and values.
+                let mut values = collect(arg).into_iter();
+                let mut pairs = Vec::new();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    pairs.push((key, value));
+                }
+
+                Value::Dict(pairs)
+            }
+            ArgType::Array => Value::Array(collect(arg)),
+            ArgType::Struct | ArgType::DictEntry => Value::Struct(collect(arg)),
+            ArgType::Variant => match arg.as_iter().and_then(|mut iter| iter.next()) {
+                Some(inner) => Value::Variant(Box::new(Value::from(inner))),
+                None => Value::Unsupported((&*arg.signature()).into()),
+            },
+            _ => Value::Unsupported((&*arg.signature()).into()),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::VersionFormat;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+};
+
+/// The format that `fu_common_vercmp` parses versions with, when their format is not known.
+pub(crate) const DEFAULT_FORMAT: VersionFormat = VersionFormat::Triplet;
+
+/// A firmware version, which is compared the same way as libfwupd's `fu_common_vercmp`.
+///
+/// Versions that the daemon reports as a single integer are converted to a dotted version
+/// according to their `VersionFormat` before being compared. Versions without a format are
+/// converted as triplets, as `fu_common_vercmp` does.
+#[derive(Clone, Debug)]
+pub struct Version {
+    raw:        Box<str>,
+    normalized: Box<str>,
+}
+
+impl Version {
+    /// Parse a version according to the given format.
+    pub fn new(version: &str, format: VersionFormat) -> Self {
+        Version { raw: version.into(), normalized: parse_from_format(version, format).into() }
+    }
+
+    /// The version as it was given.
+    pub fn as_str(&self) -> &str { &self.raw }
+
+    /// The version in dotted form, which is used for comparisons.
+    pub fn normalized(&self) -> &str { &self.normalized }
+}
+
+impl<'a> From<&'a str> for Version {
+    fn from(version: &'a str) -> Self { Version::new(version, DEFAULT_FORMAT) }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result { f.write_str(&self.raw) }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering { vercmp(&self.normalized, &other.normalized) }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Version {}
+
+/// Compares two versions of the same format.
+pub(crate) fn compare(a: &str, b: &str, format: VersionFormat) -> Ordering {
+    vercmp(&parse_from_format(a, format), &parse_from_format(b, format))
+}
+
+/// Based on `fu_common_version_parse_from_format` in libfwupd.
+fn parse_from_format(version: &str, format: VersionFormat) -> String {
+    if version.contains('.') {
+        return version.to_owned();
+    }
+
+    // Versions such as `20200412` are dates, rather than integers to convert.
+    if version.starts_with("20") && version.len() == 8 {
+        return version.to_owned();
+    }
+
+    let value = if version.starts_with("0x") {
+        u64::from_str_radix(&version[2..], 16)
+    } else if version.bytes().all(|byte| byte.is_ascii_digit()) {
+        version.parse::<u64>()
+    } else {
+        return version.to_owned();
+    };
+
+    match value {
+        Ok(value) if value != 0 => {
+            from_u32(value as u32, format).unwrap_or_else(|| version.to_owned())
+        }
+        _ => version.to_owned(),
+    }
+}
+
+/// Based on `fu_common_version_from_uint32` in libfwupd.
+fn from_u32(value: u32, format: VersionFormat) -> Option<String> {
+    fn bcd(value: u32) -> u32 { ((value >> 4) & 0x0f) * 10 + (value & 0x0f) }
+
+    let version = match format {
+        VersionFormat::Quad => format!(
+            "{}.{}.{}.{}",
+            (value >> 24) & 0xff,
+            (value >> 16) & 0xff,
+            (value >> 8) & 0xff,
+            value & 0xff
+        ),
+        VersionFormat::Triplet => {
+            format!("{}.{}.{}", (value >> 24) & 0xff, (value >> 16) & 0xff, value & 0xffff)
+        }
+        VersionFormat::Pair => format!("{}.{}", (value >> 16) & 0xffff, value & 0xffff),
+        VersionFormat::Number | VersionFormat::Plain => value.to_string(),
+        VersionFormat::Bcd => format!(
+            "{}.{}.{}.{}",
+            bcd(value >> 24),
+            bcd(value >> 16),
+            bcd(value >> 8),
+            bcd(value)
+        ),
+        VersionFormat::IntelMe => format!(
+            "{}.{}.{}.{}",
+            ((value >> 29) & 0x07) + 0x0b,
+            (value >> 24) & 0x1f,
+            (value >> 16) & 0xff,
+            value & 0xffff
+        ),
+        VersionFormat::IntelMe2 => format!(
+            "{}.{}.{}.{}",
+            (value >> 28) & 0x0f,
+            (value >> 24) & 0x0f,
+            (value >> 16) & 0xff,
+            value & 0xffff
+        ),
+        VersionFormat::Unknown => return None,
+    };
+
+    Some(version)
+}
+
+/// Based on `fu_common_vercmp` in libfwupd.
+fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let (mut split_a, mut split_b) = (a.split('.'), b.split('.'));
+    loop {
+        let (section_a, section_b) = match (split_a.next(), split_b.next()) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return Ordering::Equal,
+        };
+
+        let (number_a, rest_a) = strtoll(section_a);
+        let (number_b, rest_b) = strtoll(section_b);
+
+        match number_a.cmp(&number_b) {
+            Ordering::Equal => (),
+            ordering => return ordering,
+        }
+
+        match rest_a.cmp(rest_b) {
+            Ordering::Equal => (),
+            ordering => return ordering,
+        }
+    }
+}
+
+/// Parses the leading integer of a section, returning the unparsed remainder, as `strtoll` does.
+fn strtoll(section: &str) -> (i64, &str) {
+    let trimmed = section.trim_start();
+    let bytes = trimmed.as_bytes();
+
+    let negative = bytes.first() == Some(&b'-');
+    let start = if negative || bytes.first() == Some(&b'+') { 1 } else { 0 };
+    let end = start + bytes[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+
+    if end == start {
+        return (0, section);
+    }
+
+    let number = match trimmed[start..end].parse::<i64>() {
+        Ok(number) if negative => -number,
+        Ok(number) => number,
+        Err(_) if negative => std::i64::MIN,
+        Err(_) => std::i64::MAX,
+    };
+
+    (number, &trimmed[end..])
+}